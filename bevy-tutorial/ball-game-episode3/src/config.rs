@@ -0,0 +1,104 @@
+//! Gameplay tunables, loaded from `assets/config/game.ron` and hot-reloaded whenever that file
+//! changes, so the feel of the game can be tuned without recompiling.
+
+use std::io;
+
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, LoadContext};
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{Enemy, Player};
+
+pub const GAME_CONFIG_PATH: &str = "config/game.ron";
+
+/// The values in `Default` are only used until `assets/config/game.ron` has loaded.
+#[derive(Asset, TypePath, Resource, Deserialize, Debug, Clone, PartialEq)]
+pub struct GameConfig {
+    pub player_speed: f32,
+    pub player_size: f32,
+    pub enemy_size: f32,
+    pub number_of_enemies: usize,
+}
+
+impl Default for GameConfig {
+    fn default() -> GameConfig {
+        GameConfig {
+            player_speed: 500.0,
+            player_size: 64.0,
+            enemy_size: 64.0,
+            number_of_enemies: 4,
+        }
+    }
+}
+
+#[derive(Resource, Deref)]
+pub struct GameConfigHandle(Handle<GameConfig>);
+
+#[derive(Default)]
+pub struct GameConfigLoader;
+
+impl AssetLoader for GameConfigLoader {
+    type Asset = GameConfig;
+    type Settings = ();
+    type Error = io::Error;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<GameConfig, io::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        ron::de::from_bytes(&bytes).map_err(io::Error::other)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["ron"]
+    }
+}
+
+pub fn load_game_config(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(GameConfigHandle(asset_server.load(GAME_CONFIG_PATH)));
+}
+
+/// Whether `assets/config/game.ron` has loaded, so `GameConfig` holds its values.
+pub fn game_config_loaded(handle: Option<Res<GameConfigHandle>>, game_configs: Res<Assets<GameConfig>>) -> bool {
+    handle.is_some_and(|handle| game_configs.contains(handle.id()))
+}
+
+/// Copies the config asset into the `GameConfig` resource the systems read, every time it is
+/// loaded or edited, and resizes the sprites that are already on screen.
+pub fn apply_game_config(
+    mut asset_events: EventReader<AssetEvent<GameConfig>>,
+    game_configs: Res<Assets<GameConfig>>,
+    handle: Option<Res<GameConfigHandle>>,
+    mut game_config: ResMut<GameConfig>,
+    mut player_query: Query<&mut Sprite, (With<Player>, Without<Enemy>)>,
+    mut enemy_query: Query<&mut Sprite, (With<Enemy>, Without<Player>)>,
+) {
+    let Some(handle) = handle else {
+        return;
+    };
+
+    for event in asset_events.read() {
+        let (AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id }) = event else {
+            continue;
+        };
+        if *id != handle.id() {
+            continue;
+        }
+        let Some(config) = game_configs.get(*id) else {
+            continue;
+        };
+
+        *game_config = config.clone();
+        for mut sprite in player_query.iter_mut() {
+            sprite.custom_size = Some(Vec2::splat(game_config.player_size));
+        }
+        for mut sprite in enemy_query.iter_mut() {
+            sprite.custom_size = Some(Vec2::splat(game_config.enemy_size));
+        }
+    }
+}
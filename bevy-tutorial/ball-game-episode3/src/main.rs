@@ -1,17 +1,27 @@
 use bevy::prelude::*;
 use rand::prelude::*;
 
-pub const PLAYER_SPEED: f32 = 500.0;
-pub const PLAYER_SIZE: f32 = 64.0; // This is the player sprite size.
-pub const ENEMY_SIZE: f32 = 64.0; // This is the enemy sprite size.
-pub const NUMBER_OF_ENEMIES: usize = 4;
+mod config;
+
+use config::GameConfig;
 
 fn main() {
     App::new()
         .add_plugins(DefaultPlugins)
+        .init_resource::<GameConfig>()
+        .init_asset::<GameConfig>()
+        .init_asset_loader::<config::GameConfigLoader>()
+        .add_systems(Startup, config::load_game_config)
         .add_systems(Startup, spawn_camera)
         .add_systems(Startup, spawn_player)
-        .add_systems(Startup, spawn_enemies)
+        .add_systems(Update, config::apply_game_config)
+        .add_systems(
+            Update,
+            spawn_enemies
+                .after(config::apply_game_config)
+                .run_if(config::game_config_loaded)
+                .run_if(resource_changed::<GameConfig>),
+        )
         .add_systems(Update, player_movement)
         .add_systems(Update, confine_player_movement)
         .run();
@@ -26,11 +36,14 @@ pub struct Enemy;
 pub fn spawn_player(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
+    game_config: Res<GameConfig>,
 ) {
     commands.spawn((
-      Sprite::from_image(
-            asset_server.load("sprites/ball_blue_large.png"),
-        ),
+        Sprite {
+            image: asset_server.load("sprites/ball_blue_large.png"),
+            custom_size: Some(Vec2::splat(game_config.player_size)),
+            ..default()
+        },
         Player,
     ));
 }
@@ -41,22 +54,33 @@ pub fn spawn_camera(mut commands: Commands) {
     );
 }
 
+/// Spawns or despawns enemies until there are as many as the config asks for, once it has loaded
+/// and again whenever it is edited.
 pub fn spawn_enemies(
     mut commands: Commands,
     windows: Query<&Window>,
+    enemy_query: Query<Entity, With<Enemy>>,
     asset_server: Res<AssetServer>,
+    game_config: Res<GameConfig>,
 ) {
     let window = windows.single();
 
-    let half_enemy_size = PLAYER_SIZE / 2.0; 
-    for _ in 0..NUMBER_OF_ENEMIES {
+    for enemy in enemy_query.iter().skip(game_config.number_of_enemies) {
+        commands.entity(enemy).despawn();
+    }
+
+    let half_enemy_size = game_config.enemy_size / 2.0;
+    let missing = game_config.number_of_enemies.saturating_sub(enemy_query.iter().count());
+    for _ in 0..missing {
         let random_x = random::<f32>() * window.width() - window.width() / 2.0 + half_enemy_size;
         let random_y = random::<f32>() * window.height() - window.height() / 2.0 + half_enemy_size;
 
         commands.spawn((
-            Sprite::from_image(
-              asset_server.load("sprites/ball_red_large.png"),
-            ),
+            Sprite {
+                image: asset_server.load("sprites/ball_red_large.png"),
+                custom_size: Some(Vec2::splat(game_config.enemy_size)),
+                ..default()
+            },
             Transform::from_xyz(random_x, random_y, 0.0),
             Enemy,
         ));
@@ -67,6 +91,7 @@ pub fn player_movement(
     keyboard_input: Res<ButtonInput<KeyCode>>,
     mut player_query: Query<&mut Transform, With<Player>>,
     time: Res<Time>,
+    game_config: Res<GameConfig>,
 ) {
     if let Ok(mut transform) = player_query.get_single_mut() {
         let mut direction = Vec3::ZERO;
@@ -88,18 +113,19 @@ pub fn player_movement(
             direction = direction.normalize();
         }
 
-        transform.translation += direction * PLAYER_SPEED * time.delta().as_secs_f32();
+        transform.translation += direction * game_config.player_speed * time.delta().as_secs_f32();
     }
 }
 
 pub fn confine_player_movement(
     mut player_query: Query<&mut Transform, With<Player>>,
     windows: Query<&Window>,
+    game_config: Res<GameConfig>,
 ) {
     if let Ok(mut player_transform) = player_query.get_single_mut() {
         let window = windows.single();
 
-        let half_player_size = PLAYER_SIZE / 2.0;
+        let half_player_size = game_config.player_size / 2.0;
         let x_min = - window.width() / 2.0 + half_player_size;
         let x_max = window.width() / 2.0 - half_player_size;
         let y_min = - window.height() / 2.0 + half_player_size;
@@ -0,0 +1,42 @@
+//! Times `Particles::update` headless, with no window or GPU. Run with `cargo bench`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use ball_game::particles::{ParticleEffect, ParticlePresets, Particles, MAX_PARTICLES};
+use bevy::math::Vec2;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+const FRAMES: u32 = 1000;
+const DT: f32 = 1.0 / 60.0;
+
+fn main() {
+    let mut preset = ParticlePresets::default().presets[&ParticleEffect::PlayerDeath].clone();
+    // Keep every particle alive for the whole run, so each frame updates all of them.
+    preset.lifetime = (1000.0, 1000.0);
+    let mut rng = ChaCha8Rng::seed_from_u64(0);
+
+    for count in [1000, 4000, MAX_PARTICLES] {
+        let mut particles = Particles::default();
+        while particles.particles.len() < count {
+            particles.emit(&mut rng, ParticleEffect::PlayerDeath, &preset, Vec2::ZERO, Vec2::ZERO);
+        }
+        particles.particles.truncate(count);
+
+        let start = Instant::now();
+        for _ in 0..FRAMES {
+            black_box(&mut particles).update(DT);
+        }
+        let elapsed = start.elapsed();
+
+        let per_frame = elapsed / FRAMES;
+        println!(
+            "{:>5} particles: {:>8.1?} per frame, {:>5.1} ns per particle, {:.1}% of a 60 Hz frame",
+            count,
+            per_frame,
+            per_frame.as_nanos() as f64 / count as f64,
+            per_frame.as_secs_f64() / Duration::from_secs_f32(DT).as_secs_f64() * 100.0,
+        );
+    }
+}
@@ -0,0 +1,91 @@
+//! The rectangle the game is played in. The arena has a fixed size in logical units, whatever the
+//! size or DPI of the window, and the camera scales it to fit with bars on the sides that are left
+//! over, so the game is equally hard on every screen.
+
+use bevy::prelude::*;
+use bevy::render::camera::ScalingMode;
+use rand::prelude::*;
+
+/// The size of the arena in logical units. Positions, sizes and speeds are all in these units.
+pub const ARENA_SIZE: Vec2 = Vec2::new(1280.0, 720.0);
+/// The colour of the arena, the bars around it are black.
+pub const ARENA_COLOR: Color = Color::srgb(43.0 / 255.0, 44.0 / 255.0, 47.0 / 255.0);
+
+/// The walls of the arena, centred on the origin.
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct ArenaBounds {
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+impl Default for ArenaBounds {
+    fn default() -> ArenaBounds {
+        ArenaBounds::from_size(ARENA_SIZE.x, ARENA_SIZE.y)
+    }
+}
+
+impl ArenaBounds {
+    pub fn from_size(width: f32, height: f32) -> ArenaBounds {
+        let half_size = Vec2::new(width, height) / 2.0;
+        ArenaBounds {
+            min: -half_size,
+            max: half_size,
+        }
+    }
+
+    pub fn size(&self) -> Vec2 {
+        self.max - self.min
+    }
+
+    /// The range the centre of an object of `size` can be in while the object is fully inside.
+    /// When the arena is smaller than the object the range is empty, with `min` above `max`.
+    pub fn inset(&self, size: f32) -> (Vec2, Vec2) {
+        let half_size = size / 2.0;
+        (self.min + half_size, self.max - half_size)
+    }
+
+    /// Moves an object of `size` at `position` the shortest way back inside the arena.
+    pub fn clamp(&self, position: Vec2, size: f32) -> Vec2 {
+        let (min, max) = self.inset(size);
+        Vec2::new(clamp_axis(position.x, min.x, max.x), clamp_axis(position.y, min.y, max.y))
+    }
+
+    /// A random position that keeps an object of `size` fully inside the arena.
+    pub fn random_position(&self, rng: &mut impl Rng, size: f32) -> Vec3 {
+        let (min, _) = self.inset(size);
+        let room = self.size() - size;
+        let random_x = rng.gen::<f32>() * room.x + min.x;
+        let random_y = rng.gen::<f32>() * room.y + min.y;
+
+        Vec3::new(random_x, random_y, 0.0)
+    }
+}
+
+fn clamp_axis(position: f32, min: f32, max: f32) -> f32 {
+    if min > max {
+        // The arena is smaller than the object, the best we can do is centre it.
+        (min + max) / 2.0
+    } else {
+        position.clamp(min, max)
+    }
+}
+
+/// A projection that always shows the whole arena as large as the window allows.
+pub fn arena_projection() -> OrthographicProjection {
+    OrthographicProjection {
+        scaling_mode: ScalingMode::AutoMin {
+            min_width: ARENA_SIZE.x,
+            min_height: ARENA_SIZE.y,
+        },
+        ..OrthographicProjection::default_2d()
+    }
+}
+
+/// Draws the arena over the black clear colour, which shows as letterbox or pillarbox bars when the
+/// window has a different aspect ratio.
+pub fn spawn_arena_background(mut commands: Commands, arena_bounds: Res<ArenaBounds>) {
+    commands.spawn((
+        Sprite::from_color(ARENA_COLOR, arena_bounds.size()),
+        Transform::from_translation(((arena_bounds.min + arena_bounds.max) / 2.0).extend(-1.0)),
+    ));
+}
@@ -0,0 +1,461 @@
+//! Sound effects with master, SFX and music volume channels.
+//!
+//! Gameplay asks for a sound by sending a `PlaySfx` event. Each sound effect caps how many copies
+//! of itself play at once and how soon it may play again, so a crowded arena does not turn into a
+//! wall of overlapping plucks. Sounds sent with a position are panned and attenuated relative to
+//! the `SpatialListener` on the camera, so a hit on the far left is heard on the left.
+//!
+//! Every channel has its own volume actions. Anything that plays with the `Music` marker follows
+//! the music volume. The volumes are kept in `ball-game/volume.ron` under the platform config
+//! directory (`$XDG_CONFIG_HOME` on Linux).
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use bevy::audio::{SpatialScale, Volume};
+use bevy::prelude::*;
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::input::{Action, ActionState};
+
+/// Bumped whenever the layout of `VolumeSettings` changes.
+pub const VOLUME_SETTINGS_VERSION: u32 = 1;
+pub const VOLUME_STEP: f32 = 0.1;
+/// The distance between the listener's ears, in world units.
+pub const LISTENER_EAR_GAP: f32 = 600.0;
+/// Scales world units so that a sound within half the ear gap of an ear plays at full volume and
+/// fades with the square of the distance beyond that.
+pub const SPATIAL_SCALE: SpatialScale = SpatialScale::new_2d(2.0 / LISTENER_EAR_GAP);
+
+/// Plays the sound effects gameplay asks for, at the volumes the player chose.
+pub struct AudioFxPlugin;
+
+impl Plugin for AudioFxPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Volumes>()
+            .init_resource::<SfxCooldowns>()
+            .add_event::<PlaySfx>()
+            .add_systems(Startup, load_sfx_clips)
+            .add_systems(
+                Update,
+                (
+                    adjust_volumes,
+                    (apply_volumes, update_volume_text).run_if(resource_changed::<Volumes>),
+                    play_sound_effects,
+                )
+                    .chain(),
+            );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Sfx {
+    WallHit,
+    StarPickup,
+    PlayerHit,
+    Explosion,
+    Laser,
+    EnemyDestroyed,
+}
+
+/// How a sound effect is played. One of the `clips` is picked at random every time, and its
+/// playback speed, and with it the pitch, varies by up to `pitch_variance` around `speed`.
+pub struct SfxDefinition {
+    pub clips: &'static [&'static str],
+    pub max_instances: usize,
+    pub cooldown: Duration,
+    pub speed: f32,
+    pub pitch_variance: f32,
+}
+
+impl Sfx {
+    pub const ALL: [Sfx; 6] = [
+        Sfx::WallHit,
+        Sfx::StarPickup,
+        Sfx::PlayerHit,
+        Sfx::Explosion,
+        Sfx::Laser,
+        Sfx::EnemyDestroyed,
+    ];
+
+    pub fn definition(self) -> SfxDefinition {
+        match self {
+            Sfx::WallHit => SfxDefinition {
+                clips: &["audio/pluck_001.ogg", "audio/pluck_002.ogg"],
+                max_instances: 3,
+                cooldown: Duration::from_millis(80),
+                speed: 1.0,
+                pitch_variance: 0.15,
+            },
+            Sfx::StarPickup => SfxDefinition {
+                clips: &["audio/pluck_002.ogg"],
+                max_instances: 2,
+                cooldown: Duration::from_millis(50),
+                speed: 1.5,
+                pitch_variance: 0.05,
+            },
+            Sfx::PlayerHit => SfxDefinition {
+                clips: &["audio/explosionCrunch_000.ogg"],
+                max_instances: 1,
+                cooldown: Duration::ZERO,
+                speed: 1.8,
+                pitch_variance: 0.1,
+            },
+            Sfx::Explosion => SfxDefinition {
+                clips: &["audio/explosionCrunch_000.ogg"],
+                max_instances: 1,
+                cooldown: Duration::ZERO,
+                speed: 1.0,
+                pitch_variance: 0.0,
+            },
+            Sfx::Laser => SfxDefinition {
+                clips: &["audio/laserLarge_001.ogg"],
+                max_instances: 4,
+                cooldown: Duration::from_millis(50),
+                speed: 1.0,
+                pitch_variance: 0.08,
+            },
+            Sfx::EnemyDestroyed => SfxDefinition {
+                clips: &["audio/explosionCrunch_000.ogg"],
+                max_instances: 3,
+                cooldown: Duration::from_millis(50),
+                speed: 1.4,
+                pitch_variance: 0.15,
+            },
+        }
+    }
+}
+
+/// Asks for a sound effect, optionally at a position in the world to pan it by.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct PlaySfx {
+    pub sfx: Sfx,
+    pub position: Option<Vec2>,
+}
+
+impl PlaySfx {
+    pub fn centered(sfx: Sfx) -> PlaySfx {
+        PlaySfx { sfx, position: None }
+    }
+
+    pub fn at(sfx: Sfx, position: Vec2) -> PlaySfx {
+        PlaySfx {
+            sfx,
+            position: Some(position),
+        }
+    }
+}
+
+/// Marks a playing sound effect, so the number of copies of each one can be capped.
+#[derive(Component)]
+pub struct SfxInstance(pub Sfx);
+
+/// Marks music, which plays on the music channel.
+#[derive(Component)]
+pub struct Music;
+
+/// The HUD line with the volume of every channel.
+#[derive(Component)]
+pub struct VolumeText;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VolumeChannel {
+    Master,
+    Sfx,
+    Music,
+}
+
+impl VolumeChannel {
+    pub const ALL: [VolumeChannel; 3] = [VolumeChannel::Master, VolumeChannel::Sfx, VolumeChannel::Music];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            VolumeChannel::Master => "Master",
+            VolumeChannel::Sfx => "SFX",
+            VolumeChannel::Music => "Music",
+        }
+    }
+
+    /// The actions that step this channel up and down.
+    pub fn actions(self) -> (Action, Action) {
+        match self {
+            VolumeChannel::Master => (Action::MasterVolumeUp, Action::MasterVolumeDown),
+            VolumeChannel::Sfx => (Action::SfxVolumeUp, Action::SfxVolumeDown),
+            VolumeChannel::Music => (Action::MusicVolumeUp, Action::MusicVolumeDown),
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct SfxClips {
+    pub clips: HashMap<Sfx, Vec<Handle<AudioSource>>>,
+}
+
+/// When every sound effect last played, in real time so that pausing does not hold sounds back.
+#[derive(Resource, Default)]
+pub struct SfxCooldowns {
+    pub last_played: HashMap<Sfx, Duration>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct VolumeSettings {
+    pub version: u32,
+    pub master: f32,
+    pub sfx: f32,
+    pub music: f32,
+}
+
+impl VolumeSettings {
+    pub fn volume(&self, channel: VolumeChannel) -> f32 {
+        match channel {
+            VolumeChannel::Master => self.master,
+            VolumeChannel::Sfx => self.sfx,
+            VolumeChannel::Music => self.music,
+        }
+    }
+
+    pub fn volume_mut(&mut self, channel: VolumeChannel) -> &mut f32 {
+        match channel {
+            VolumeChannel::Master => &mut self.master,
+            VolumeChannel::Sfx => &mut self.sfx,
+            VolumeChannel::Music => &mut self.music,
+        }
+    }
+}
+
+impl Default for VolumeSettings {
+    fn default() -> VolumeSettings {
+        VolumeSettings {
+            version: VOLUME_SETTINGS_VERSION,
+            master: 1.0,
+            sfx: 1.0,
+            music: 0.5,
+        }
+    }
+}
+
+/// The volume settings together with where they are stored. Without a path the settings only
+/// live for as long as the game runs.
+#[derive(Resource, Debug, Default)]
+pub struct Volumes {
+    pub path: Option<PathBuf>,
+    pub settings: VolumeSettings,
+}
+
+impl Volumes {
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|config_dir| config_dir.join("ball-game").join("volume.ron"))
+    }
+
+    /// Loads the settings at `path`. Missing or corrupt settings fall back to the defaults.
+    pub fn load(path: Option<PathBuf>) -> Volumes {
+        let settings = path.as_deref().and_then(load_settings).unwrap_or_default();
+        Volumes { path, settings }
+    }
+
+    pub fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        let result = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| {
+                let contents = ron::to_string(&self.settings).map_err(std::io::Error::other)?;
+                fs::write(path, contents)
+            });
+        if let Err(error) = result {
+            eprintln!("Could not save volume settings to {}: {}", path.display(), error);
+        }
+    }
+
+    pub fn sfx_volume(&self) -> f32 {
+        self.settings.master * self.settings.sfx
+    }
+
+    pub fn music_volume(&self) -> f32 {
+        self.settings.master * self.settings.music
+    }
+}
+
+fn load_settings(path: &Path) -> Option<VolumeSettings> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(error) => {
+            eprintln!("Could not read volume settings from {}: {}", path.display(), error);
+            return None;
+        }
+    };
+
+    match ron::from_str::<VolumeSettings>(&contents) {
+        Ok(settings) if settings.version == VOLUME_SETTINGS_VERSION => Some(settings),
+        Ok(settings) => {
+            eprintln!(
+                "Ignoring volume settings in {}, version {} is not supported",
+                path.display(),
+                settings.version
+            );
+            None
+        }
+        Err(error) => {
+            eprintln!("Ignoring corrupt volume settings in {}: {}", path.display(), error);
+            None
+        }
+    }
+}
+
+pub fn load_sfx_clips(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let clips = Sfx::ALL
+        .into_iter()
+        .map(|sfx| {
+            let handles = sfx.definition().clips.iter().map(|clip| asset_server.load(*clip)).collect();
+            (sfx, handles)
+        })
+        .collect();
+    commands.insert_resource(SfxClips { clips });
+}
+
+/// Whether a sound effect may start, given when it last played and how many copies are playing.
+pub fn can_play(definition: &SfxDefinition, now: Duration, last_played: Option<Duration>, playing: usize) -> bool {
+    if playing >= definition.max_instances {
+        return false;
+    }
+    last_played.is_none_or(|last_played| now.saturating_sub(last_played) >= definition.cooldown)
+}
+
+pub fn play_sound_effects(
+    mut commands: Commands,
+    mut events: EventReader<PlaySfx>,
+    sfx_clips: Option<Res<SfxClips>>,
+    mut cooldowns: ResMut<SfxCooldowns>,
+    instances: Query<&SfxInstance>,
+    volumes: Res<Volumes>,
+    time: Res<Time<Real>>,
+) {
+    let Some(sfx_clips) = sfx_clips else {
+        events.clear();
+        return;
+    };
+
+    let mut playing: HashMap<Sfx, usize> = HashMap::new();
+    for instance in instances.iter() {
+        *playing.entry(instance.0).or_default() += 1;
+    }
+
+    // Audio variation is cosmetic, so it draws from the thread RNG rather than `GameRng` and
+    // cannot change how a seed plays out.
+    let mut rng = thread_rng();
+    let now = time.elapsed();
+    for &PlaySfx { sfx, position } in events.read() {
+        let definition = sfx.definition();
+        let count = playing.entry(sfx).or_default();
+        if !can_play(&definition, now, cooldowns.last_played.get(&sfx).copied(), *count) {
+            continue;
+        }
+        let Some(clip) = sfx_clips.clips.get(&sfx).and_then(|clips| clips.choose(&mut rng)) else {
+            continue;
+        };
+
+        let speed = definition.speed + rng.gen_range(-1.0..=1.0) * definition.pitch_variance;
+        let settings = PlaybackSettings::DESPAWN
+            .with_volume(Volume::new(volumes.sfx_volume()))
+            .with_speed(speed);
+        let mut sound = commands.spawn((AudioPlayer(clip.clone()), SfxInstance(sfx)));
+        match position {
+            Some(position) => sound.insert((
+                settings.with_spatial(true).with_spatial_scale(SPATIAL_SCALE),
+                Transform::from_translation(position.extend(0.0)),
+            )),
+            None => sound.insert(settings),
+        };
+        *count += 1;
+        cooldowns.last_played.insert(sfx, now);
+    }
+}
+
+/// Steps every channel with its volume actions and stores the new settings.
+pub fn adjust_volumes(action_state: Res<ActionState>, mut volumes: ResMut<Volumes>) {
+    let mut changed = false;
+    for channel in VolumeChannel::ALL {
+        let (up, down) = channel.actions();
+        let step = if action_state.just_pressed(up) {
+            VOLUME_STEP
+        } else if action_state.just_pressed(down) {
+            -VOLUME_STEP
+        } else {
+            continue;
+        };
+        let volume = volumes.settings.volume_mut(channel);
+        *volume = (*volume + step).clamp(0.0, 1.0);
+        changed = true;
+    }
+
+    if changed {
+        volumes.save();
+    }
+}
+
+/// Applies volume changes to the sounds that are already playing.
+pub fn apply_volumes(
+    volumes: Res<Volumes>,
+    sfx_query: Query<&AudioSink, (With<SfxInstance>, Without<Music>)>,
+    spatial_sfx_query: Query<&SpatialAudioSink, (With<SfxInstance>, Without<Music>)>,
+    music_query: Query<&AudioSink, (With<Music>, Without<SfxInstance>)>,
+) {
+    for sink in sfx_query.iter() {
+        sink.set_volume(volumes.sfx_volume());
+    }
+    for sink in spatial_sfx_query.iter() {
+        sink.set_volume(volumes.sfx_volume());
+    }
+    for sink in music_query.iter() {
+        sink.set_volume(volumes.music_volume());
+    }
+}
+
+pub fn update_volume_text(volumes: Res<Volumes>, mut text_query: Query<&mut Text, With<VolumeText>>) {
+    for mut text in text_query.iter_mut() {
+        let channels: Vec<String> = VolumeChannel::ALL
+            .into_iter()
+            .map(|channel| format!("{} {:.0}%", channel.label(), volumes.settings.volume(channel) * 100.0))
+            .collect();
+        **text = format!("Volume: {}", channels.join(", "));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sound_effects_are_capped_and_cooled_down() {
+        let definition = Sfx::WallHit.definition();
+        let now = Duration::from_secs(10);
+
+        assert!(can_play(&definition, now, None, 0));
+        assert!(!can_play(&definition, now, None, definition.max_instances));
+        assert!(!can_play(&definition, now, Some(now - definition.cooldown / 2), 0));
+        assert!(can_play(&definition, now, Some(now - definition.cooldown), 0));
+    }
+
+    #[test]
+    fn volume_settings_round_trip_and_survive_corruption() {
+        let directory = std::env::temp_dir().join(format!("ball-game-volume-{}", std::process::id()));
+        let path = directory.join("volume.ron");
+        let mut volumes = Volumes::load(Some(path.clone()));
+        assert_eq!(volumes.settings, VolumeSettings::default());
+
+        volumes.settings.master = 0.3;
+        volumes.save();
+        assert_eq!(Volumes::load(Some(path.clone())).settings, volumes.settings);
+
+        fs::write(&path, "loud").unwrap();
+        assert_eq!(Volumes::load(Some(path)).settings, VolumeSettings::default());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
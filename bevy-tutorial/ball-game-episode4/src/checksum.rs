@@ -0,0 +1,82 @@
+//! A 64-bit FNV-1a hash over bytes written in a fixed order and layout, for checksums that are
+//! compared between runs, builds and machines. Unlike `DefaultHasher`, it is the same everywhere.
+
+use std::time::Duration;
+
+use bevy::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checksum {
+    hash: u64,
+}
+
+impl Default for Checksum {
+    fn default() -> Checksum {
+        Checksum {
+            hash: Checksum::OFFSET_BASIS,
+        }
+    }
+}
+
+impl Checksum {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.hash ^= byte as u64;
+            self.hash = self.hash.wrapping_mul(Checksum::PRIME);
+        }
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn f32(&mut self, value: f32) {
+        self.u32(value.to_bits());
+    }
+
+    pub fn vec2(&mut self, value: Vec2) {
+        self.f32(value.x);
+        self.f32(value.y);
+    }
+
+    pub fn vec3(&mut self, value: Vec3) {
+        self.f32(value.x);
+        self.f32(value.y);
+        self.f32(value.z);
+    }
+
+    pub fn duration(&mut self, value: Duration) {
+        self.u64(value.as_secs());
+        self.u32(value.subsec_nanos());
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+/// The checksum of `bytes` alone.
+pub fn checksum_of(bytes: &[u8]) -> u64 {
+    let mut checksum = Checksum::default();
+    checksum.bytes(bytes);
+    checksum.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_the_published_fnv1a_values() {
+        assert_eq!(checksum_of(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(checksum_of(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(checksum_of(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+}
@@ -0,0 +1,295 @@
+//! Gameplay tunables, loaded from `assets/config/game.ron` and hot-reloaded whenever that file
+//! changes, so the feel of the game can be tuned without recompiling.
+//!
+//! A run that is recorded, played back or played online has to play out the same way everywhere,
+//! so an edit made during one of those only takes effect when the next run starts.
+
+use std::fmt;
+
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, LoadContext};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::checksum::checksum_of;
+use crate::netplay::NetplaySession;
+use crate::replay::ReplayMode;
+use crate::{arg_value, ArgError, Enemy, GameState, Player};
+
+pub const GAME_CONFIG_PATH: &str = "config/game.ron";
+
+/// The values in `Default` are only used until `assets/config/game.ron` has loaded.
+#[derive(Asset, TypePath, Resource, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GameConfig {
+    pub player_speed: f32,
+    pub player_size: f32,
+    pub enemy_speed: f32,
+    pub enemy_size: f32,
+    pub number_of_enemies: usize,
+    /// How far the left stick has to be pushed, from 0 to 1, before the player moves.
+    pub gamepad_deadzone: f32,
+    pub player_lives: u32,
+    /// How long the player cannot be hit again after losing a life, in seconds.
+    pub invulnerability_time: f32,
+    /// How fast a hit pushes the player away from the enemy.
+    pub knockback_speed: f32,
+    /// How fast a chaser can turn toward the player, in radians a second.
+    pub chaser_turn_rate: f32,
+    /// How fast the noise can turn a wanderer, in radians a second.
+    pub wanderer_turn_rate: f32,
+    /// How many times a splitter hits the walls before it breaks in two.
+    pub splitter_bounces: u32,
+    pub enemy_mix: EnemyMix,
+}
+
+impl Default for GameConfig {
+    fn default() -> GameConfig {
+        GameConfig {
+            player_speed: 500.0,
+            player_size: 64.0,
+            enemy_speed: 200.0,
+            enemy_size: 64.0,
+            number_of_enemies: 4,
+            gamepad_deadzone: 0.15,
+            player_lives: 3,
+            invulnerability_time: 2.0,
+            knockback_speed: 900.0,
+            chaser_turn_rate: 1.5,
+            wanderer_turn_rate: 3.0,
+            splitter_bounces: 3,
+            enemy_mix: EnemyMix {
+                easy: EnemyWeights {
+                    bouncer: 6,
+                    chaser: 1,
+                    wanderer: 3,
+                    splitter: 0,
+                },
+                normal: EnemyWeights {
+                    bouncer: 4,
+                    chaser: 2,
+                    wanderer: 2,
+                    splitter: 2,
+                },
+                hard: EnemyWeights {
+                    bouncer: 2,
+                    chaser: 4,
+                    wanderer: 1,
+                    splitter: 3,
+                },
+            },
+        }
+    }
+}
+
+impl GameConfig {
+    /// Runs only play out the same way with the same tunables, so replays keep this checksum of
+    /// them to tell when the config has changed since the recording.
+    pub fn checksum(&self) -> u64 {
+        // RON writes the fields in order and floats exactly, so equal configs give equal text.
+        checksum_of(ron::to_string(self).expect("the game config always serializes").as_bytes())
+    }
+}
+
+/// Chosen with `--difficulty easy|normal|hard`, it decides which kinds of enemies a run gets.
+#[derive(Resource, Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+}
+
+impl Difficulty {
+    pub fn from_args() -> Result<Difficulty, ArgError> {
+        Difficulty::from_arg(arg_value("--difficulty").as_deref())
+    }
+
+    pub fn from_arg(value: Option<&str>) -> Result<Difficulty, ArgError> {
+        match value {
+            None => Ok(Difficulty::default()),
+            Some("easy") => Ok(Difficulty::Easy),
+            Some("normal") => Ok(Difficulty::Normal),
+            Some("hard") => Ok(Difficulty::Hard),
+            Some(other) => Err(ArgError::new("--difficulty", other, "easy, normal or hard")),
+        }
+    }
+}
+
+/// The odds of every kind of enemy, for each difficulty.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EnemyMix {
+    pub easy: EnemyWeights,
+    pub normal: EnemyWeights,
+    pub hard: EnemyWeights,
+}
+
+impl EnemyMix {
+    pub fn weights(&self, difficulty: Difficulty) -> &EnemyWeights {
+        match difficulty {
+            Difficulty::Easy => &self.easy,
+            Difficulty::Normal => &self.normal,
+            Difficulty::Hard => &self.hard,
+        }
+    }
+}
+
+/// How likely each kind of enemy is, relative to the others. A weight of 0 turns a kind off.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct EnemyWeights {
+    pub bouncer: u32,
+    pub chaser: u32,
+    pub wanderer: u32,
+    pub splitter: u32,
+}
+
+#[derive(Resource, Deref)]
+pub struct GameConfigHandle(Handle<GameConfig>);
+
+#[derive(Default)]
+pub struct GameConfigLoader;
+
+#[derive(Debug)]
+pub enum GameConfigLoaderError {
+    Io(std::io::Error),
+    Format(ron::error::SpannedError),
+}
+
+impl fmt::Display for GameConfigLoaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GameConfigLoaderError::Io(error) => write!(f, "could not read the game config: {}", error),
+            GameConfigLoaderError::Format(error) => write!(f, "the game config is malformed: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for GameConfigLoaderError {}
+
+impl From<std::io::Error> for GameConfigLoaderError {
+    fn from(error: std::io::Error) -> GameConfigLoaderError {
+        GameConfigLoaderError::Io(error)
+    }
+}
+
+impl From<ron::error::SpannedError> for GameConfigLoaderError {
+    fn from(error: ron::error::SpannedError) -> GameConfigLoaderError {
+        GameConfigLoaderError::Format(error)
+    }
+}
+
+impl AssetLoader for GameConfigLoader {
+    type Asset = GameConfig;
+    type Settings = ();
+    type Error = GameConfigLoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<GameConfig, GameConfigLoaderError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        Ok(ron::de::from_bytes(&bytes)?)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["ron"]
+    }
+}
+
+pub fn load_game_config(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(GameConfigHandle(asset_server.load(GAME_CONFIG_PATH)));
+}
+
+/// Copies the config asset into the `GameConfig` resource the systems read, every time it is
+/// loaded or edited, and resizes the sprites that are already on screen. A run that has to play out
+/// the same way everywhere keeps its config until `refresh_game_config` picks up the edit.
+#[allow(clippy::too_many_arguments)]
+pub fn apply_game_config(
+    mut asset_events: EventReader<AssetEvent<GameConfig>>,
+    game_configs: Res<Assets<GameConfig>>,
+    handle: Option<Res<GameConfigHandle>>,
+    mut game_config: ResMut<GameConfig>,
+    mut player_query: Query<&mut Sprite, (With<Player>, Without<Enemy>)>,
+    mut enemy_query: Query<&mut Sprite, (With<Enemy>, Without<Player>)>,
+    state: Res<State<GameState>>,
+    replay_mode: Res<ReplayMode>,
+    netplay: Option<Res<NetplaySession>>,
+) {
+    let Some(handle) = handle else {
+        return;
+    };
+    let in_run = matches!(state.get(), GameState::InGame | GameState::Paused);
+    if in_run && (!matches!(*replay_mode, ReplayMode::Off) || netplay.is_some()) {
+        asset_events.clear();
+        return;
+    }
+
+    for event in asset_events.read() {
+        let (AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id }) = event else {
+            continue;
+        };
+        if *id != handle.id() {
+            continue;
+        }
+        let Some(config) = game_configs.get(*id) else {
+            continue;
+        };
+
+        *game_config = config.clone();
+        for mut sprite in player_query.iter_mut() {
+            sprite.custom_size = Some(Vec2::splat(game_config.player_size));
+        }
+        for mut sprite in enemy_query.iter_mut() {
+            sprite.custom_size = Some(Vec2::splat(game_config.enemy_size));
+        }
+    }
+}
+
+/// Takes up the latest config at the start of every run, including an edit that was held back
+/// while the last run had to play out the same way everywhere.
+pub fn refresh_game_config(
+    game_configs: Res<Assets<GameConfig>>,
+    handle: Option<Res<GameConfigHandle>>,
+    mut game_config: ResMut<GameConfig>,
+) {
+    let Some(config) = handle.and_then(|handle| game_configs.get(&**handle)) else {
+        return;
+    };
+    if *game_config != *config {
+        *game_config = config.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shipped_config_matches_the_defaults() {
+        let config: GameConfig = ron::from_str(include_str!("../assets/config/game.ron")).unwrap();
+
+        assert_eq!(config, GameConfig::default());
+    }
+
+    #[test]
+    fn unknown_difficulty_is_an_error() {
+        assert_eq!(Difficulty::from_arg(None), Ok(Difficulty::Normal));
+        assert_eq!(Difficulty::from_arg(Some("hard")), Ok(Difficulty::Hard));
+        assert!(Difficulty::from_arg(Some("medium")).is_err());
+    }
+
+    #[test]
+    fn checksum_changes_with_any_tunable() {
+        let config = GameConfig::default();
+        let mut faster = config.clone();
+        faster.enemy_speed += 1.0;
+        let mut harder = config.clone();
+        harder.enemy_mix.hard.chaser += 1;
+
+        assert_eq!(config.checksum(), config.clone().checksum());
+        assert_ne!(config.checksum(), faster.checksum());
+        assert_ne!(config.checksum(), harder.checksum());
+    }
+}
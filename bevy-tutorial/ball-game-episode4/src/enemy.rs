@@ -0,0 +1,499 @@
+//! Enemies: how they spawn, how they steer and bounce around the arena and the wall hits they
+//! report.
+
+use std::f32::consts::{PI, TAU};
+
+use bevy::prelude::*;
+use rand::prelude::*;
+
+use crate::arena::ArenaBounds;
+use crate::audio::{PlaySfx, Sfx};
+use crate::config::{Difficulty, EnemyWeights, GameConfig};
+use crate::{player, GameRng, GameSet, GameState, Player};
+
+pub const ENEMY_SPAWN_INTERVAL: f32 = 5.0;
+pub const ENEMY_SPAWN_MIN_INTERVAL: f32 = 1.0;
+pub const ENEMY_SPAWN_INTERVAL_DECAY: f32 = 0.9;
+pub const ENEMY_SPEED_GROWTH: f32 = 10.0;
+pub const ENEMY_MAX_SPEED: f32 = 400.0;
+pub const MAX_ENEMIES: usize = 30;
+/// How big the two halves of a splitter are, relative to the splitter.
+pub const SPLIT_SCALE: f32 = 0.6;
+/// How far the halves of a splitter fly apart from its heading.
+pub const SPLIT_ANGLE: f32 = PI / 6.0;
+/// How quickly the noise steering a wanderer changes, in cycles a second.
+pub const WANDER_FREQUENCY: f32 = 0.8;
+
+/// Moves enemies, spawns more of them over time and plays their wall hits.
+pub struct EnemyPlugin;
+
+impl Plugin for EnemyPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<EnemySpawnConfig>()
+            .init_resource::<EnemySpawner>()
+            .init_resource::<Time<EnemyClock>>()
+            .add_event::<EnemyWallCollisionEvent>()
+            .add_systems(
+                FixedUpdate,
+                (
+                    // Chasers steer toward where the player has just moved to.
+                    (advance_enemy_clock, steer_enemies, enemy_movement, split_enemies)
+                        .chain()
+                        .in_set(GameSet::Movement)
+                        .after(player::confine_player_movement),
+                    (tick_enemy_spawner, spawn_enemies_over_time).chain().in_set(GameSet::Spawn),
+                ),
+            )
+            .add_systems(Update, enemy_wall_collision.run_if(in_state(GameState::InGame)));
+    }
+}
+
+/// The context of the `Time<EnemyClock>` enemies move by. It advances by `relative_speed` times
+/// the fixed timestep, so effects such as slow motion slow down the enemies and nothing else.
+#[derive(Debug, Clone, Copy)]
+pub struct EnemyClock {
+    pub relative_speed: f32,
+}
+
+impl Default for EnemyClock {
+    fn default() -> EnemyClock {
+        EnemyClock { relative_speed: 1.0 }
+    }
+}
+
+/// An enemy, as large as `GameConfig::enemy_size` times the scale of its transform.
+#[derive(Component, Clone)]
+pub struct Enemy {
+    pub direction: Vec2,
+    pub speed: f32,
+}
+
+/// How an enemy moves. An enemy without one flies straight like a bouncer.
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub enum EnemyBehaviour {
+    /// Flies straight and bounces off the walls.
+    Bouncer,
+    /// Turns toward the nearest player, at most `GameConfig::chaser_turn_rate` a second.
+    Chaser,
+    /// Drifts about, turned by smooth noise. `noise_time` is where it is along the noise.
+    Wanderer { noise_time: f32 },
+    /// Breaks into two smaller bouncers once it has hit the walls `bounces_left` more times.
+    Splitter { bounces_left: u32 },
+}
+
+impl EnemyBehaviour {
+    /// A behaviour picked with the odds of `weights`, a bouncer if every weight is 0.
+    pub fn random(rng: &mut impl Rng, weights: &EnemyWeights, game_config: &GameConfig) -> EnemyBehaviour {
+        let total = weights.bouncer + weights.chaser + weights.wanderer + weights.splitter;
+        if total == 0 {
+            return EnemyBehaviour::Bouncer;
+        }
+
+        let mut pick = rng.gen_range(0..total);
+        if pick < weights.bouncer {
+            return EnemyBehaviour::Bouncer;
+        }
+        pick -= weights.bouncer;
+        if pick < weights.chaser {
+            return EnemyBehaviour::Chaser;
+        }
+        pick -= weights.chaser;
+        if pick < weights.wanderer {
+            // Start every wanderer somewhere else along the noise so they do not turn in unison.
+            return EnemyBehaviour::Wanderer {
+                noise_time: rng.gen_range(0.0..1000.0),
+            };
+        }
+        EnemyBehaviour::Splitter {
+            bounces_left: game_config.splitter_bounces,
+        }
+    }
+
+    pub fn tint(&self) -> Color {
+        match self {
+            EnemyBehaviour::Bouncer => Color::WHITE,
+            EnemyBehaviour::Chaser => Color::srgb(1.0, 0.6, 0.2),
+            EnemyBehaviour::Wanderer { .. } => Color::srgb(0.6, 1.0, 0.6),
+            EnemyBehaviour::Splitter { .. } => Color::srgb(0.8, 0.6, 1.0),
+        }
+    }
+}
+
+/// Tuning for the timer-driven enemy spawner. Every spawn shrinks the interval by
+/// `interval_decay` down to `min_interval`, and each new enemy is `speed_growth` faster than the
+/// previous one, up to `max_speed`.
+#[derive(Resource)]
+pub struct EnemySpawnConfig {
+    pub interval: f32,
+    pub min_interval: f32,
+    pub interval_decay: f32,
+    pub max_enemies: usize,
+    pub speed_growth: f32,
+    pub max_speed: f32,
+}
+
+impl Default for EnemySpawnConfig {
+    fn default() -> EnemySpawnConfig {
+        EnemySpawnConfig {
+            interval: ENEMY_SPAWN_INTERVAL,
+            min_interval: ENEMY_SPAWN_MIN_INTERVAL,
+            interval_decay: ENEMY_SPAWN_INTERVAL_DECAY,
+            max_enemies: MAX_ENEMIES,
+            speed_growth: ENEMY_SPEED_GROWTH,
+            max_speed: ENEMY_MAX_SPEED,
+        }
+    }
+}
+
+/// Per-run state of the enemy spawner, reset at the start of every run.
+#[derive(Resource, Clone)]
+pub struct EnemySpawner {
+    pub timer: Timer,
+    pub speed: f32,
+}
+
+impl Default for EnemySpawner {
+    fn default() -> EnemySpawner {
+        EnemySpawner {
+            timer: Timer::from_seconds(ENEMY_SPAWN_INTERVAL, TimerMode::Once),
+            speed: GameConfig::default().enemy_speed,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WallSide {
+    Left,
+    Right,
+    Bottom,
+    Top,
+}
+
+/// An enemy bounced off a wall of the arena. `contact` is where its edge touched the wall.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct EnemyWallCollisionEvent {
+    pub enemy: Entity,
+    pub side: WallSide,
+    pub contact: Vec2,
+}
+
+pub fn reset_enemy_spawner(
+    mut enemy_spawner: ResMut<EnemySpawner>,
+    enemy_spawn_config: Res<EnemySpawnConfig>,
+    game_config: Res<GameConfig>,
+) {
+    *enemy_spawner = EnemySpawner {
+        timer: Timer::from_seconds(enemy_spawn_config.interval, TimerMode::Once),
+        speed: game_config.enemy_speed,
+    };
+}
+
+pub fn spawn_enemies(
+    mut commands: Commands,
+    arena_bounds: Res<ArenaBounds>,
+    player_query: Query<&Transform, With<Player>>,
+    asset_server: Res<AssetServer>,
+    mut rng: ResMut<GameRng>,
+    game_config: Res<GameConfig>,
+    difficulty: Res<Difficulty>,
+) {
+    let player_positions: Vec<Vec3> = player_query.iter().map(|transform| transform.translation).collect();
+
+    for _ in 0..game_config.number_of_enemies {
+        if let Some(position) = random_enemy_position(&mut **rng, &game_config, &arena_bounds, &player_positions) {
+            let weights = game_config.enemy_mix.weights(*difficulty);
+            let behaviour = EnemyBehaviour::random(&mut **rng, weights, &game_config);
+            let speed = game_config.enemy_speed;
+            spawn_enemy(&mut commands, &asset_server, &mut **rng, &game_config, position, speed, behaviour);
+        }
+    }
+}
+
+/// A random enemy position that does not overlap any player, or `None` if the arena is too
+/// crowded to find one.
+pub fn random_enemy_position(
+    rng: &mut impl Rng,
+    game_config: &GameConfig,
+    arena_bounds: &ArenaBounds,
+    player_positions: &[Vec3],
+) -> Option<Vec3> {
+    const MAX_ATTEMPTS: usize = 16;
+    // Leave a full player width of room so a new enemy cannot hit the player on its first frame.
+    let safe_distance = game_config.player_size + game_config.enemy_size / 2.0;
+
+    (0..MAX_ATTEMPTS)
+        .map(|_| arena_bounds.random_position(rng, game_config.enemy_size))
+        .find(|position| {
+            player_positions
+                .iter()
+                .all(|player_position| position.distance(*player_position) >= safe_distance)
+        })
+}
+
+pub fn spawn_enemy(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    rng: &mut impl Rng,
+    game_config: &GameConfig,
+    position: Vec3,
+    speed: f32,
+    behaviour: EnemyBehaviour,
+) {
+    let direction = Vec2::from_angle(rng.gen_range(0.0..TAU));
+    let transform = Transform::from_translation(position);
+    commands.spawn(enemy_bundle(asset_server, game_config, transform, direction, speed, behaviour));
+}
+
+pub(crate) fn enemy_bundle(
+    asset_server: &AssetServer,
+    game_config: &GameConfig,
+    transform: Transform,
+    direction: Vec2,
+    speed: f32,
+    behaviour: EnemyBehaviour,
+) -> impl Bundle {
+    (
+        Sprite {
+            image: asset_server.load("sprites/ball_red_large.png"),
+            custom_size: Some(Vec2::splat(game_config.enemy_size)),
+            color: behaviour.tint(),
+            ..default()
+        },
+        transform,
+        Enemy { direction, speed },
+        behaviour,
+    )
+}
+
+pub fn tick_enemy_spawner(
+    mut enemy_spawner: ResMut<EnemySpawner>,
+    enemy_spawn_config: Res<EnemySpawnConfig>,
+    time: Res<Time>,
+) {
+    if enemy_spawner.timer.finished() {
+        let interval = (enemy_spawner.timer.duration().as_secs_f32() * enemy_spawn_config.interval_decay)
+            .max(enemy_spawn_config.min_interval);
+        enemy_spawner.timer = Timer::from_seconds(interval, TimerMode::Once);
+    }
+    enemy_spawner.timer.tick(time.delta());
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_enemies_over_time(
+    mut commands: Commands,
+    arena_bounds: Res<ArenaBounds>,
+    player_query: Query<&Transform, With<Player>>,
+    enemy_query: Query<(), With<Enemy>>,
+    asset_server: Res<AssetServer>,
+    enemy_spawn_config: Res<EnemySpawnConfig>,
+    mut enemy_spawner: ResMut<EnemySpawner>,
+    mut rng: ResMut<GameRng>,
+    game_config: Res<GameConfig>,
+    difficulty: Res<Difficulty>,
+) {
+    if !enemy_spawner.timer.just_finished() {
+        return;
+    }
+
+    if enemy_query.iter().count() >= enemy_spawn_config.max_enemies {
+        return;
+    }
+
+    let player_positions: Vec<Vec3> = player_query.iter().map(|transform| transform.translation).collect();
+    if let Some(position) = random_enemy_position(&mut **rng, &game_config, &arena_bounds, &player_positions) {
+        enemy_spawner.speed = (enemy_spawner.speed + enemy_spawn_config.speed_growth)
+            .min(enemy_spawn_config.max_speed);
+        let weights = game_config.enemy_mix.weights(*difficulty);
+        let behaviour = EnemyBehaviour::random(&mut **rng, weights, &game_config);
+        let speed = enemy_spawner.speed;
+        spawn_enemy(&mut commands, &asset_server, &mut **rng, &game_config, position, speed, behaviour);
+    }
+}
+
+pub fn advance_enemy_clock(time: Res<Time>, mut enemy_time: ResMut<Time<EnemyClock>>) {
+    let relative_speed = enemy_time.context().relative_speed;
+    enemy_time.advance_by(time.delta().mul_f32(relative_speed));
+}
+
+/// Turns chasers toward the nearest player and wanderers wherever the noise takes them.
+pub fn steer_enemies(
+    mut enemy_query: Query<(&Transform, &mut Enemy, &mut EnemyBehaviour)>,
+    player_query: Query<&Transform, With<Player>>,
+    time: Res<Time<EnemyClock>>,
+    game_config: Res<GameConfig>,
+) {
+    let dt = time.delta_secs();
+
+    for (transform, mut enemy, mut behaviour) in enemy_query.iter_mut() {
+        let position = transform.translation.truncate();
+        match &mut *behaviour {
+            EnemyBehaviour::Bouncer | EnemyBehaviour::Splitter { .. } => {}
+            EnemyBehaviour::Chaser => {
+                let nearest_player = player_query
+                    .iter()
+                    .map(|player_transform| player_transform.translation.truncate())
+                    .min_by(|a, b| a.distance_squared(position).total_cmp(&b.distance_squared(position)));
+                if let Some(player_position) = nearest_player {
+                    let max_turn = game_config.chaser_turn_rate * dt;
+                    let turn = enemy.direction.angle_to(player_position - position);
+                    if turn.is_finite() {
+                        enemy.direction = turn_by(enemy.direction, turn.clamp(-max_turn, max_turn));
+                    }
+                }
+            }
+            EnemyBehaviour::Wanderer { noise_time } => {
+                *noise_time += dt;
+                let turn = value_noise(*noise_time * WANDER_FREQUENCY) * game_config.wanderer_turn_rate * dt;
+                enemy.direction = turn_by(enemy.direction, turn);
+            }
+        }
+    }
+}
+
+fn turn_by(direction: Vec2, angle: f32) -> Vec2 {
+    Vec2::from_angle(angle).rotate(direction).normalize_or(direction)
+}
+
+/// Smooth one dimensional noise between -1 and 1: random values at whole numbers, eased into each
+/// other in between. The same `x` always gives the same value, so wanderers replay exactly.
+pub fn value_noise(x: f32) -> f32 {
+    let cell = x.floor();
+    let t = x - cell;
+    let t = t * t * (3.0 - 2.0 * t);
+    let a = lattice_value(cell as i32);
+    let b = lattice_value(cell as i32 + 1);
+    a + (b - a) * t
+}
+
+/// A hash of `i` mapped to -1..=1.
+fn lattice_value(i: i32) -> f32 {
+    let mut hash = (i as u32).wrapping_mul(0x9E37_79B1);
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(0x85EB_CA77);
+    hash ^= hash >> 13;
+    hash as f32 / u32::MAX as f32 * 2.0 - 1.0
+}
+
+/// Moves enemies and resolves their wall collisions in one step, so an enemy can never be
+/// left outside the arena with its direction pointing further out.
+pub fn enemy_movement(
+    mut enemy_query: Query<(Entity, &mut Transform, &mut Enemy, Option<&mut EnemyBehaviour>)>,
+    arena_bounds: Res<ArenaBounds>,
+    mut collision_events: EventWriter<EnemyWallCollisionEvent>,
+    time: Res<Time<EnemyClock>>,
+    game_config: Res<GameConfig>,
+) {
+    for (entity, mut transform, mut enemy, behaviour) in enemy_query.iter_mut() {
+        let enemy_size = game_config.enemy_size * transform.scale.x;
+        let half_enemy_size = enemy_size / 2.0;
+        let (min, max) = arena_bounds.inset(enemy_size);
+
+        let velocity = enemy.direction * enemy.speed;
+        let (position, velocity, hit_wall) =
+            sweep_circle_in_bounds(transform.translation.truncate(), velocity, time.delta_secs(), min, max);
+
+        transform.translation = position.extend(transform.translation.z);
+        enemy.direction = velocity.normalize_or(enemy.direction);
+
+        if let Some(side) = hit_wall {
+            if let Some(mut behaviour) = behaviour {
+                if let EnemyBehaviour::Splitter { bounces_left } = &mut *behaviour {
+                    *bounces_left = bounces_left.saturating_sub(1);
+                }
+            }
+
+            let contact = match side {
+                WallSide::Left => Vec2::new(min.x - half_enemy_size, position.y),
+                WallSide::Right => Vec2::new(max.x + half_enemy_size, position.y),
+                WallSide::Bottom => Vec2::new(position.x, min.y - half_enemy_size),
+                WallSide::Top => Vec2::new(position.x, max.y + half_enemy_size),
+            };
+            collision_events.send(EnemyWallCollisionEvent {
+                enemy: entity,
+                side,
+                contact,
+            });
+        }
+    }
+}
+
+/// Breaks every splitter that has run out of bounces into two smaller bouncers, flying apart
+/// either side of its heading.
+pub fn split_enemies(
+    mut commands: Commands,
+    enemy_query: Query<(Entity, &Transform, &Enemy, &EnemyBehaviour)>,
+    asset_server: Res<AssetServer>,
+    game_config: Res<GameConfig>,
+) {
+    for (entity, transform, enemy, behaviour) in enemy_query.iter() {
+        if *behaviour != (EnemyBehaviour::Splitter { bounces_left: 0 }) {
+            continue;
+        }
+
+        let scale = transform.scale * Vec3::new(SPLIT_SCALE, SPLIT_SCALE, 1.0);
+        let offset = enemy.direction.perp() * game_config.enemy_size * scale.x / 2.0;
+        for side in [1.0, -1.0] {
+            let half = Transform::from_translation(transform.translation + (offset * side).extend(0.0))
+                .with_scale(scale);
+            let direction = turn_by(enemy.direction, SPLIT_ANGLE * side);
+            let behaviour = EnemyBehaviour::Bouncer;
+            commands.spawn(enemy_bundle(&asset_server, &game_config, half, direction, enemy.speed, behaviour));
+        }
+        commands.entity(entity).despawn();
+    }
+}
+
+/// Moves a circle whose centre must stay within `min..=max` by `velocity * dt`, reflecting it off
+/// any wall it crosses. Returns the new position, the new velocity and the wall that was hit, if
+/// any. In a corner the side wall is reported.
+///
+/// The velocity is reflected by the side of the wall the circle penetrated, not toggled, so a
+/// circle that starts outside the bounds always heads back in rather than oscillating on the edge.
+pub fn sweep_circle_in_bounds(
+    position: Vec2,
+    velocity: Vec2,
+    dt: f32,
+    min: Vec2,
+    max: Vec2,
+) -> (Vec2, Vec2, Option<WallSide>) {
+    let (x, velocity_x, hit_x) = sweep_axis(position.x, velocity.x, dt, min.x, max.x, [WallSide::Left, WallSide::Right]);
+    let (y, velocity_y, hit_y) = sweep_axis(position.y, velocity.y, dt, min.y, max.y, [WallSide::Bottom, WallSide::Top]);
+
+    (Vec2::new(x, y), Vec2::new(velocity_x, velocity_y), hit_x.or(hit_y))
+}
+
+/// Sweeps one axis, whose walls at `min` and `max` are `walls`.
+fn sweep_axis(
+    position: f32,
+    velocity: f32,
+    dt: f32,
+    min: f32,
+    max: f32,
+    walls: [WallSide; 2],
+) -> (f32, f32, Option<WallSide>) {
+    if min > max {
+        // The arena is smaller than the circle, the best we can do is centre it.
+        return ((min + max) / 2.0, velocity, None);
+    }
+
+    let position = position + velocity * dt;
+    if position < min {
+        // Mirror the part of the move that went past the wall back into the arena.
+        let reflected = (min + (min - position)).min(max);
+        (reflected, velocity.abs(), Some(walls[0]))
+    } else if position > max {
+        let reflected = (max - (position - max)).max(min);
+        (reflected, -velocity.abs(), Some(walls[1]))
+    } else {
+        (position, velocity, None)
+    }
+}
+
+pub fn enemy_wall_collision(
+    mut collision_events: EventReader<EnemyWallCollisionEvent>,
+    mut sfx_events: EventWriter<PlaySfx>,
+) {
+    for event in collision_events.read() {
+        sfx_events.send(PlaySfx::at(Sfx::WallHit, event.contact));
+    }
+}
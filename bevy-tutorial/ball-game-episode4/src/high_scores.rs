@@ -0,0 +1,208 @@
+//! The best runs, kept in `ball-game/high_scores.ron` under the platform data directory
+//! (`$XDG_DATA_HOME` on Linux).
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{Score, SurvivalTime};
+
+/// Bumped whenever the layout of `HighScoreTable` changes.
+pub const HIGH_SCORES_VERSION: u32 = 1;
+pub const NUMBER_OF_HIGH_SCORES: usize = 5;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HighScore {
+    pub score: u32,
+    pub seconds: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HighScoreTable {
+    pub version: u32,
+    /// Best first, at most `NUMBER_OF_HIGH_SCORES` long.
+    pub entries: Vec<HighScore>,
+}
+
+impl Default for HighScoreTable {
+    fn default() -> HighScoreTable {
+        HighScoreTable {
+            version: HIGH_SCORES_VERSION,
+            entries: Vec::new(),
+        }
+    }
+}
+
+impl HighScoreTable {
+    /// Adds a run to the table, ranked by score and then by survival time. Returns whether it
+    /// made the table.
+    pub fn insert(&mut self, entry: HighScore) -> bool {
+        let rank = self
+            .entries
+            .iter()
+            .position(|other| (entry.score, entry.seconds) > (other.score, other.seconds))
+            .unwrap_or(self.entries.len());
+        if rank >= NUMBER_OF_HIGH_SCORES {
+            return false;
+        }
+
+        self.entries.insert(rank, entry);
+        self.entries.truncate(NUMBER_OF_HIGH_SCORES);
+        true
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        if self.entries.is_empty() {
+            return vec!["No high scores yet".to_string()];
+        }
+
+        let mut lines = vec!["High Scores".to_string()];
+        lines.extend(self.entries.iter().enumerate().map(|(index, entry)| {
+            format!("{}. {} points in {}s", index + 1, entry.score, entry.seconds)
+        }));
+        lines
+    }
+}
+
+/// The high-score table together with where it is stored. Without a path the table only lives
+/// for as long as the game runs.
+#[derive(Resource, Debug, Default)]
+pub struct HighScores {
+    pub path: Option<PathBuf>,
+    pub table: HighScoreTable,
+}
+
+impl HighScores {
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::data_dir().map(|data_dir| data_dir.join("ball-game").join("high_scores.ron"))
+    }
+
+    /// Loads the table at `path`. A missing file is an empty table, and so is a corrupt one, which
+    /// is reported and then replaced on the next save.
+    pub fn load(path: Option<PathBuf>) -> HighScores {
+        let table = path.as_deref().map(load_table).unwrap_or_default();
+        HighScores { path, table }
+    }
+
+    pub fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        let result = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| {
+                let contents = ron::to_string(&self.table).map_err(std::io::Error::other)?;
+                fs::write(path, contents)
+            });
+        if let Err(error) = result {
+            eprintln!("Could not save high scores to {}: {}", path.display(), error);
+        }
+    }
+}
+
+fn load_table(path: &Path) -> HighScoreTable {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return HighScoreTable::default(),
+        Err(error) => {
+            eprintln!("Could not read high scores from {}: {}", path.display(), error);
+            return HighScoreTable::default();
+        }
+    };
+
+    match ron::from_str::<HighScoreTable>(&contents) {
+        Ok(table) if table.version == HIGH_SCORES_VERSION => table,
+        Ok(table) => {
+            eprintln!(
+                "Ignoring high scores in {}, version {} is not supported",
+                path.display(),
+                table.version
+            );
+            HighScoreTable::default()
+        }
+        Err(error) => {
+            eprintln!("Ignoring corrupt high scores in {}: {}", path.display(), error);
+            HighScoreTable::default()
+        }
+    }
+}
+
+pub fn record_high_score(mut high_scores: ResMut<HighScores>, score: Res<Score>, survival_time: Res<SurvivalTime>) {
+    let entry = HighScore {
+        score: score.value,
+        seconds: survival_time.seconds,
+    };
+    if high_scores.table.insert(entry) {
+        high_scores.save();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(score: u32, seconds: u32) -> HighScore {
+        HighScore { score, seconds }
+    }
+
+    #[test]
+    fn table_keeps_the_best_entries_in_order() {
+        let mut table = HighScoreTable::default();
+        for score in [3, 9, 1, 7, 5, 8, 2] {
+            table.insert(entry(score, 10));
+        }
+
+        let scores: Vec<u32> = table.entries.iter().map(|entry| entry.score).collect();
+        assert_eq!(scores, vec![9, 8, 7, 5, 3]);
+        assert!(!table.insert(entry(1, 10)));
+    }
+
+    #[test]
+    fn ties_are_broken_by_survival_time() {
+        let mut table = HighScoreTable::default();
+        table.insert(entry(4, 10));
+        table.insert(entry(4, 30));
+
+        assert_eq!(table.entries, vec![entry(4, 30), entry(4, 10)]);
+    }
+
+    #[test]
+    fn lines_list_points_and_survival_time() {
+        let mut table = HighScoreTable::default();
+        table.insert(entry(12, 40));
+
+        assert_eq!(table.lines(), vec!["High Scores", "1. 12 points in 40s"]);
+    }
+
+    #[test]
+    fn missing_or_corrupt_files_load_as_empty() {
+        let directory = std::env::temp_dir().join(format!("ball-game-high-scores-{}", std::process::id()));
+        let path = directory.join("high_scores.ron");
+        assert_eq!(HighScores::load(Some(path.clone())).table, HighScoreTable::default());
+
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(&path, "not a high score table").unwrap();
+        assert_eq!(HighScores::load(Some(path.clone())).table, HighScoreTable::default());
+
+        fs::write(&path, "(version: 99, entries: [(score: 1, seconds: 1)])").unwrap();
+        assert_eq!(HighScores::load(Some(path.clone())).table, HighScoreTable::default());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn table_round_trips_through_file() {
+        let directory = std::env::temp_dir().join(format!("ball-game-high-scores-save-{}", std::process::id()));
+        let path = directory.join("nested").join("high_scores.ron");
+        let mut high_scores = HighScores::load(Some(path.clone()));
+        high_scores.table.insert(entry(6, 42));
+        high_scores.save();
+
+        assert_eq!(HighScores::load(Some(path)).table, high_scores.table);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,416 @@
+//! Maps keyboard, mouse and gamepad input to game actions, so gameplay never looks at raw key
+//! codes.
+//!
+//! Every player has a seat with its own movement and fire actions. In co-op the first player has
+//! WASD and the mouse, the second the arrow keys (the `P2` actions). A single gamepad drives the
+//! second player, and with two connected the first gamepad drives the first player. Playing alone,
+//! every key set and gamepad drives the one player.
+//!
+//! The default bindings can be overridden per action in `ball-game/controls.ron` under the
+//! platform config directory (`$XDG_CONFIG_HOME` on Linux), for example:
+//!
+//! ```ron
+//! (version: 1, bindings: { MoveUp: [Key(KeyI)], Pause: [Key(KeyP), GamepadButton(Start)] })
+//! ```
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+use serde::{Deserialize, Serialize};
+
+use crate::config::GameConfig;
+use crate::netplay::NetplaySession;
+use crate::player::{PlayerCount, MAX_PLAYERS};
+
+/// Bumped whenever the layout of `ControlsFile` changes.
+pub const CONTROLS_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    Pause,
+    Confirm,
+    Back,
+    MasterVolumeUp,
+    MasterVolumeDown,
+    SfxVolumeUp,
+    SfxVolumeDown,
+    MusicVolumeUp,
+    MusicVolumeDown,
+    Fire,
+    P2MoveUp,
+    P2MoveDown,
+    P2MoveLeft,
+    P2MoveRight,
+    P2Fire,
+}
+
+impl Action {
+    pub const ALL: [Action; 19] = [
+        Action::MoveUp,
+        Action::MoveDown,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::Pause,
+        Action::Confirm,
+        Action::Back,
+        Action::MasterVolumeUp,
+        Action::MasterVolumeDown,
+        Action::SfxVolumeUp,
+        Action::SfxVolumeDown,
+        Action::MusicVolumeUp,
+        Action::MusicVolumeDown,
+        Action::Fire,
+        Action::P2MoveUp,
+        Action::P2MoveDown,
+        Action::P2MoveLeft,
+        Action::P2MoveRight,
+        Action::P2Fire,
+    ];
+
+    /// The seat a movement or fire action belongs to, and the first player's action it stands for
+    /// in that seat.
+    pub fn seat_action(self) -> Option<(usize, Action)> {
+        match self {
+            Action::MoveUp | Action::MoveDown | Action::MoveLeft | Action::MoveRight | Action::Fire => Some((0, self)),
+            Action::P2MoveUp => Some((1, Action::MoveUp)),
+            Action::P2MoveDown => Some((1, Action::MoveDown)),
+            Action::P2MoveLeft => Some((1, Action::MoveLeft)),
+            Action::P2MoveRight => Some((1, Action::MoveRight)),
+            Action::P2Fire => Some((1, Action::Fire)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Binding {
+    Key(KeyCode),
+    GamepadButton(GamepadButton),
+    MouseButton(MouseButton),
+}
+
+/// The bindings of every action.
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct InputMap {
+    pub bindings: HashMap<Action, Vec<Binding>>,
+}
+
+impl Default for InputMap {
+    fn default() -> InputMap {
+        use Binding::{GamepadButton as Button, Key, MouseButton as Mouse};
+
+        let bindings = HashMap::from([
+            (Action::MoveUp, vec![Key(KeyCode::KeyW), Button(GamepadButton::DPadUp)]),
+            (Action::MoveDown, vec![Key(KeyCode::KeyS), Button(GamepadButton::DPadDown)]),
+            (Action::MoveLeft, vec![Key(KeyCode::KeyA), Button(GamepadButton::DPadLeft)]),
+            (Action::MoveRight, vec![Key(KeyCode::KeyD), Button(GamepadButton::DPadRight)]),
+            (Action::Pause, vec![Key(KeyCode::Escape), Button(GamepadButton::Start)]),
+            (Action::Confirm, vec![Key(KeyCode::Enter), Button(GamepadButton::South)]),
+            (Action::Back, vec![Key(KeyCode::Escape), Button(GamepadButton::East)]),
+            (Action::MasterVolumeUp, vec![Key(KeyCode::Equal), Button(GamepadButton::RightTrigger)]),
+            (Action::MasterVolumeDown, vec![Key(KeyCode::Minus), Button(GamepadButton::LeftTrigger)]),
+            (Action::SfxVolumeUp, vec![Key(KeyCode::BracketRight)]),
+            (Action::SfxVolumeDown, vec![Key(KeyCode::BracketLeft)]),
+            (Action::MusicVolumeUp, vec![Key(KeyCode::Quote)]),
+            (Action::MusicVolumeDown, vec![Key(KeyCode::Semicolon)]),
+            (Action::Fire, vec![Mouse(MouseButton::Left), Key(KeyCode::Space), Button(GamepadButton::RightTrigger2)]),
+            (Action::P2MoveUp, vec![Key(KeyCode::ArrowUp), Button(GamepadButton::DPadUp)]),
+            (Action::P2MoveDown, vec![Key(KeyCode::ArrowDown), Button(GamepadButton::DPadDown)]),
+            (Action::P2MoveLeft, vec![Key(KeyCode::ArrowLeft), Button(GamepadButton::DPadLeft)]),
+            (Action::P2MoveRight, vec![Key(KeyCode::ArrowRight), Button(GamepadButton::DPadRight)]),
+            (Action::P2Fire, vec![Key(KeyCode::ShiftRight), Button(GamepadButton::RightTrigger2)]),
+        ]);
+        InputMap { bindings }
+    }
+}
+
+/// The user's overrides, each replacing all default bindings of its action.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ControlsFile {
+    pub version: u32,
+    pub bindings: HashMap<Action, Vec<Binding>>,
+}
+
+impl InputMap {
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|config_dir| config_dir.join("ball-game").join("controls.ron"))
+    }
+
+    /// The default bindings with the overrides from `path` applied. A missing file keeps the
+    /// defaults, and so does a corrupt one, which is reported.
+    pub fn load(path: Option<&Path>) -> InputMap {
+        let mut input_map = InputMap::default();
+        if let Some(controls) = path.and_then(load_controls) {
+            input_map.bindings.extend(controls.bindings);
+        }
+        input_map
+    }
+
+    pub fn bindings(&self, action: Action) -> &[Binding] {
+        self.bindings.get(&action).map_or(&[], Vec::as_slice)
+    }
+}
+
+fn load_controls(path: &Path) -> Option<ControlsFile> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(error) => {
+            eprintln!("Could not read controls from {}: {}", path.display(), error);
+            return None;
+        }
+    };
+
+    match ron::from_str::<ControlsFile>(&contents) {
+        Ok(controls) if controls.version == CONTROLS_VERSION => Some(controls),
+        Ok(controls) => {
+            eprintln!(
+                "Ignoring controls in {}, version {} is not supported",
+                path.display(),
+                controls.version
+            );
+            None
+        }
+        Err(error) => {
+            eprintln!("Ignoring corrupt controls in {}: {}", path.display(), error);
+            None
+        }
+    }
+}
+
+/// The state of every action this frame from any device, what each seat asks for and where the
+/// mouse cursor is in the world.
+#[derive(Resource, Debug, Default)]
+pub struct ActionState {
+    pub buttons: ButtonInput<Action>,
+    pub cursor: Option<Vec2>,
+    pub seats: [SeatState; MAX_PLAYERS],
+}
+
+impl ActionState {
+    pub fn pressed(&self, action: Action) -> bool {
+        self.buttons.pressed(action)
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.buttons.just_pressed(action)
+    }
+}
+
+/// The movement and fire actions of one player's devices, as the first player's actions, plus the
+/// analog movement from the left stick and the aim of the right stick.
+#[derive(Debug, Default)]
+pub struct SeatState {
+    pub buttons: ButtonInput<Action>,
+    pub stick: Vec2,
+    pub aim_stick: Vec2,
+}
+
+impl SeatState {
+    pub fn pressed(&self, action: Action) -> bool {
+        self.buttons.pressed(action)
+    }
+
+    /// Digital movement always moves at full speed. Otherwise the stick moves the player, scaled
+    /// by how far it is pushed past the deadzone.
+    pub fn movement(&self) -> Vec2 {
+        let mut direction = Vec2::ZERO;
+
+        if self.pressed(Action::MoveLeft) {
+            direction += Vec2::new(-1.0, 0.0);
+        }
+        if self.pressed(Action::MoveRight) {
+            direction += Vec2::new(1.0, 0.0);
+        }
+        if self.pressed(Action::MoveUp) {
+            direction += Vec2::new(0.0, 1.0);
+        }
+        if self.pressed(Action::MoveDown) {
+            direction += Vec2::new(0.0, -1.0);
+        }
+
+        if direction.length() > 0.0 {
+            direction.normalize()
+        } else {
+            self.stick
+        }
+    }
+
+    /// The direction to fire in from `position`, or `None` when not firing. Pushing the right
+    /// stick fires where it points. `Fire` fires toward the `cursor`, or the way the player is
+    /// moving without one.
+    pub fn fire_direction(&self, position: Vec2, cursor: Option<Vec2>) -> Option<Vec2> {
+        if self.aim_stick != Vec2::ZERO {
+            return Some(self.aim_stick.normalize());
+        }
+        if !self.pressed(Action::Fire) {
+            return None;
+        }
+        cursor
+            .map(|cursor| cursor - position)
+            .unwrap_or_else(|| self.movement())
+            .try_normalize()
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn update_action_state(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mouse_input: Res<ButtonInput<MouseButton>>,
+    gamepads: Query<(Entity, &Gamepad)>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    camera_query: Query<(&Camera, &GlobalTransform)>,
+    input_map: Res<InputMap>,
+    game_config: Res<GameConfig>,
+    player_count: Res<PlayerCount>,
+    netplay: Option<Res<NetplaySession>>,
+    mut action_state: ResMut<ActionState>,
+) {
+    // Online only one of the players is at this machine.
+    let player_count = if netplay.is_some() { PlayerCount(1) } else { *player_count };
+
+    // Seat gamepads in the order they were connected.
+    let mut gamepads: Vec<(Entity, &Gamepad)> = gamepads.iter().collect();
+    gamepads.sort_by_key(|(entity, _)| *entity);
+    let gamepad_seats: Vec<(usize, &Gamepad)> = gamepads
+        .iter()
+        .enumerate()
+        .map(|(index, (_, gamepad))| (gamepad_seat(index, gamepads.len(), player_count), *gamepad))
+        .collect();
+    // Playing alone every key set is the one player's.
+    let seat_of = |seat: usize| seat.min(player_count.0 - 1);
+
+    let mut seat_pressed = Vec::new();
+    action_state.buttons.clear();
+    for action in Action::ALL {
+        let seat_action = action.seat_action().map(|(seat, base)| (seat_of(seat), base));
+        let active = input_map.bindings(action).iter().any(|binding| match *binding {
+            Binding::Key(key) => keyboard_input.pressed(key),
+            Binding::MouseButton(button) => mouse_input.pressed(button),
+            // A gamepad only drives the movement and fire of its own seat.
+            Binding::GamepadButton(button) => gamepad_seats.iter().any(|(gamepad_seat, gamepad)| {
+                gamepad.pressed(button) && seat_action.is_none_or(|(seat, _)| seat == *gamepad_seat)
+            }),
+        });
+        if let Some(seat_action) = seat_action.filter(|_| active) {
+            seat_pressed.push(seat_action);
+        }
+        if active {
+            action_state.buttons.press(action);
+        } else {
+            action_state.buttons.release(action);
+        }
+    }
+
+    for (seat, seat_state) in action_state.seats.iter_mut().enumerate() {
+        seat_state.buttons.clear();
+        for action in Action::ALL {
+            if seat_pressed.contains(&(seat, action)) {
+                seat_state.buttons.press(action);
+            } else {
+                seat_state.buttons.release(action);
+            }
+        }
+
+        // With several gamepads in a seat, the stick pushed furthest wins.
+        let furthest = |stick: fn(&Gamepad) -> Vec2| {
+            gamepad_seats
+                .iter()
+                .filter(|(gamepad_seat, _)| *gamepad_seat == seat)
+                .map(|(_, gamepad)| apply_deadzone(stick(gamepad), game_config.gamepad_deadzone))
+                .max_by(|a, b| a.length_squared().total_cmp(&b.length_squared()))
+                .unwrap_or(Vec2::ZERO)
+        };
+        seat_state.stick = furthest(Gamepad::left_stick);
+        seat_state.aim_stick = furthest(Gamepad::right_stick);
+    }
+
+    action_state.cursor = window_query
+        .get_single()
+        .ok()
+        .and_then(Window::cursor_position)
+        .zip(camera_query.get_single().ok())
+        .and_then(|(cursor, (camera, camera_transform))| camera.viewport_to_world_2d(camera_transform, cursor).ok());
+}
+
+/// The seat of the `index`th of `count` connected gamepads.
+pub fn gamepad_seat(index: usize, count: usize, player_count: PlayerCount) -> usize {
+    match player_count.0 {
+        1 => 0,
+        // The first player has the keyboard and mouse, so a lone gamepad goes to the second.
+        players if count == 1 => players - 1,
+        players => index.min(players - 1),
+    }
+}
+
+/// Zeroes a stick inside the radial `deadzone` and rescales the rest so that leaving the deadzone
+/// starts from a standstill and a fully pushed stick is full speed.
+pub fn apply_deadzone(stick: Vec2, deadzone: f32) -> Vec2 {
+    let length = stick.length();
+    if length <= deadzone {
+        return Vec2::ZERO;
+    }
+
+    let scaled = ((length - deadzone) / (1.0 - deadzone)).min(1.0);
+    stick / length * scaled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overrides_replace_only_their_actions() {
+        let directory = std::env::temp_dir().join(format!("ball-game-controls-{}", std::process::id()));
+        let path = directory.join("controls.ron");
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(&path, "(version: 1, bindings: { MoveUp: [Key(KeyI)] })").unwrap();
+
+        let input_map = InputMap::load(Some(&path));
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(input_map.bindings(Action::MoveUp), &[Binding::Key(KeyCode::KeyI)]);
+        assert_eq!(input_map.bindings(Action::MoveDown), InputMap::default().bindings(Action::MoveDown));
+    }
+
+    #[test]
+    fn missing_or_corrupt_controls_keep_the_defaults() {
+        let directory = std::env::temp_dir().join(format!("ball-game-controls-corrupt-{}", std::process::id()));
+        let path = directory.join("controls.ron");
+        assert_eq!(InputMap::load(Some(&path)), InputMap::default());
+
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(&path, "(version: 1, bindings: { Jump: [] })").unwrap();
+        assert_eq!(InputMap::load(Some(&path)), InputMap::default());
+
+        fs::write(&path, "(version: 99, bindings: {})").unwrap();
+        assert_eq!(InputMap::load(Some(&path)), InputMap::default());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn a_lone_gamepad_drives_the_second_player_in_coop() {
+        assert_eq!(gamepad_seat(0, 1, PlayerCount(1)), 0);
+        assert_eq!(gamepad_seat(1, 2, PlayerCount(1)), 0);
+        assert_eq!(gamepad_seat(0, 1, PlayerCount(2)), 1);
+        assert_eq!(gamepad_seat(0, 2, PlayerCount(2)), 0);
+        assert_eq!(gamepad_seat(1, 2, PlayerCount(2)), 1);
+        assert_eq!(gamepad_seat(2, 3, PlayerCount(2)), 1);
+    }
+
+    #[test]
+    fn every_action_has_a_default_binding() {
+        let input_map = InputMap::default();
+        for action in Action::ALL {
+            assert!(!input_map.bindings(action).is_empty(), "{:?} is unbound", action);
+        }
+    }
+}
@@ -0,0 +1,687 @@
+//! The ball game as a set of Bevy plugins. Add `GamePlugin` to an app that provides the windowing,
+//! rendering and input plugins, and insert a `GameRng`, to get the whole game.
+//!
+//! `GamePlugin` is the only supported entry point. The other plugins are the parts it is made of,
+//! and rely on the config, states, events and `GameSet` order that it sets up. The components,
+//! events and system sets are public so that tools can drive the game or order their own systems
+//! around it.
+
+use std::fmt;
+
+use bevy::input::InputSystem;
+use bevy::prelude::*;
+use bevy::time::Stopwatch;
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+
+pub mod arena;
+pub mod audio;
+pub mod checksum;
+pub mod config;
+pub mod enemy;
+pub mod high_scores;
+pub mod input;
+pub mod netplay;
+pub mod particles;
+pub mod player;
+pub mod powerup;
+pub mod projectile;
+pub mod replay;
+pub mod snapshot;
+
+use arena::ArenaBounds;
+pub use audio::AudioFxPlugin;
+use audio::{PlaySfx, Sfx};
+use config::{Difficulty, GameConfig};
+pub use enemy::{Enemy, EnemyBehaviour, EnemyPlugin, EnemyWallCollisionEvent, WallSide};
+use high_scores::HighScores;
+use input::{Action, ActionState, InputMap};
+use netplay::NetplaySession;
+use particles::{EmitParticles, ParticleEffect};
+use powerup::ActivePowerUps;
+pub use netplay::NetplayPlugin;
+pub use particles::ParticlePlugin;
+pub use player::{Lives, Player, PlayerCount, PlayerInput, PlayerPlugin};
+pub use powerup::{PowerUp, PowerUpPlugin};
+pub use projectile::{Projectile, ProjectilePlugin};
+use replay::ReplayMode;
+
+pub const NUMBER_OF_STARS: usize = 10;
+pub const STAR_SIZE: f32 = 30.0;
+pub const STAR_SPAWN_TIME: f32 = 1.0;
+/// Stars stop spawning over time once this many are waiting to be collected.
+pub const MAX_STARS: usize = 20;
+
+/// The steps of every fixed tick, run in this order while the game is `InGame`.
+///
+/// The whole simulation runs in a fixed order on the fixed timestep, so a seed and the per-tick
+/// player input always replay the same way. Systems in the same step that draw from `GameRng` must
+/// be ordered among themselves as well.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum GameSet {
+    /// Fills in `PlayerInput` for the tick, live, from a replay or from the network.
+    Input,
+    Movement,
+    Collision,
+    Spawn,
+}
+
+/// Everything in the game, including `PlayerPlugin`, `EnemyPlugin`, `PowerUpPlugin`,
+/// `ProjectilePlugin`, `ParticlePlugin`, `AudioFxPlugin` and `NetplayPlugin`, which do not work
+/// without it.
+pub struct GamePlugin;
+
+impl Plugin for GamePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins((
+            PlayerPlugin,
+            EnemyPlugin,
+            PowerUpPlugin,
+            ProjectilePlugin,
+            ParticlePlugin,
+            AudioFxPlugin,
+            NetplayPlugin,
+        ))
+            .init_resource::<SeedConfig>()
+            .init_resource::<ReplayMode>()
+            .init_resource::<HighScores>()
+            .init_resource::<GameConfig>()
+            .init_resource::<Difficulty>()
+            .init_resource::<ArenaBounds>()
+            .init_resource::<InputMap>()
+            .init_resource::<ActionState>()
+            .init_asset::<GameConfig>()
+            .init_asset_loader::<config::GameConfigLoader>()
+            .init_state::<GameState>()
+            .init_resource::<Score>()
+            .init_resource::<StarSpawnTimer>()
+            .init_resource::<SurvivalTime>()
+            .init_resource::<EnemyCount>()
+            .add_systems(PreUpdate, input::update_action_state.after(InputSystem))
+            .insert_resource(ClearColor(Color::BLACK))
+            .add_systems(
+                Startup,
+                (spawn_camera, arena::spawn_arena_background, spawn_hud, config::load_game_config).chain(),
+            )
+            .add_systems(
+                OnEnter(GameState::MainMenu),
+                (despawn_game_entities, projectile::recall_projectiles, spawn_main_menu),
+            )
+            .add_systems(OnExit(GameState::MainMenu), despawn_screen::<MainMenuScreen>)
+            .add_systems(OnEnter(GameState::Paused), spawn_pause_screen)
+            .add_systems(OnExit(GameState::Paused), despawn_screen::<PauseScreen>)
+            .add_systems(
+                OnEnter(GameState::GameOver),
+                (
+                    (
+                        // Watching a replay or playing online is not a run of this player's own.
+                        high_scores::record_high_score
+                            .run_if(not(replay::is_playing_back))
+                            .run_if(not(netplay::is_online)),
+                        spawn_game_over_screen,
+                    )
+                        .chain(),
+                    replay::save_recording,
+                ),
+            )
+            .add_systems(OnExit(GameState::GameOver), despawn_screen::<GameOverScreen>)
+            // Online, the handshake starts the one run of the match and nobody can pause it.
+            .add_systems(Update, start_game.run_if(in_state(GameState::MainMenu)).run_if(not(netplay::is_online)))
+            .add_systems(
+                Update,
+                toggle_pause
+                    .run_if(in_state(GameState::InGame).or(in_state(GameState::Paused)))
+                    .run_if(not(netplay::is_online)),
+            )
+            .add_systems(Update, restart_game.run_if(in_state(GameState::GameOver)).run_if(not(netplay::is_online)))
+            .add_systems(Update, tick_survival_time.run_if(in_state(GameState::InGame)))
+            .configure_sets(
+                FixedUpdate,
+                (GameSet::Input, GameSet::Movement, GameSet::Collision, GameSet::Spawn)
+                    .chain()
+                    .run_if(in_state(GameState::InGame))
+                    .run_if(netplay::within_prediction_window),
+            )
+            .add_systems(
+                FixedUpdate,
+                (
+                    (
+                        player::read_player_input
+                            .run_if(not(replay::is_playing_back))
+                            .run_if(not(netplay::is_online)),
+                        netplay::advance_frame.run_if(netplay::is_online),
+                        replay::play_back_player_input.run_if(replay::is_playing_back),
+                        replay::record_player_input.run_if(replay::is_recording),
+                    )
+                        .chain()
+                        .in_set(GameSet::Input),
+                    player_hit_star.in_set(GameSet::Collision),
+                    (tick_star_spawn_timer, spawn_stars_over_time)
+                        .chain()
+                        .in_set(GameSet::Spawn)
+                        .before(enemy::tick_enemy_spawner),
+                ),
+            )
+            .add_systems(Last, replay::save_recording_on_exit)
+            .add_systems(Update, config::apply_game_config)
+            .add_systems(Update, count_enemies)
+            .add_systems(Update, update_score_text.run_if(resource_changed::<Score>))
+            .add_systems(Update, update_survival_time_text.run_if(resource_changed::<SurvivalTime>))
+            .add_systems(Update, update_enemy_count_text.run_if(resource_changed::<EnemyCount>))
+            .add_systems(Update, update_lives_text);
+
+        // A new run starts when entering the game from the menu or after a game over, but not when
+        // resuming from pause.
+        for exited in [GameState::MainMenu, GameState::GameOver] {
+            app.add_systems(
+                OnTransition { exited, entered: GameState::InGame },
+                (
+                    config::refresh_game_config,
+                    despawn_game_entities,
+                    projectile::recall_projectiles,
+                    reset_run,
+                    enemy::reset_enemy_spawner,
+                    powerup::reset_power_up_spawner,
+                    replay::start_replay,
+                    player::spawn_player,
+                    enemy::spawn_enemies,
+                    spawn_stars,
+                )
+                    .chain(),
+            );
+        }
+    }
+}
+
+#[derive(States, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum GameState {
+    #[default]
+    MainMenu,
+    InGame,
+    Paused,
+    GameOver,
+}
+
+#[derive(Component)]
+pub struct MainMenuScreen;
+
+#[derive(Component)]
+pub struct PauseScreen;
+
+#[derive(Component)]
+pub struct GameOverScreen;
+
+#[derive(Component)]
+pub struct Star {}
+
+/// The score of the run, shared by every player, and how much of it each player earned.
+#[derive(Resource, Default, Clone)]
+pub struct Score {
+    pub value: u32,
+    pub players: [u32; player::MAX_PLAYERS],
+}
+
+impl Score {
+    pub fn add(&mut self, player: usize, points: u32) {
+        self.value += points;
+        self.players[player] += points;
+    }
+}
+
+/// The value of a `--name <value>` or `--name=<value>` command line argument.
+pub fn arg_value(name: &str) -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    let mut value = None;
+    while let Some(arg) = args.next() {
+        if arg == name {
+            value = args.next();
+        } else if let Some(rest) = arg.strip_prefix(name).and_then(|rest| rest.strip_prefix('=')) {
+            value = Some(rest.to_string());
+        }
+    }
+    value
+}
+
+/// A command line argument with a value the game does not understand.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArgError {
+    pub name: &'static str,
+    pub value: String,
+    /// The values the argument takes, such as `easy, normal or hard`.
+    pub expected: String,
+}
+
+impl ArgError {
+    pub fn new(name: &'static str, value: &str, expected: impl Into<String>) -> ArgError {
+        ArgError {
+            name,
+            value: value.to_string(),
+            expected: expected.into(),
+        }
+    }
+}
+
+impl fmt::Display for ArgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} is not valid, it must be {}", self.name, self.value, self.expected)
+    }
+}
+
+impl std::error::Error for ArgError {}
+
+/// Seed requested with `--seed <n>` or the `BALL_GAME_SEED` environment variable. Without one,
+/// every run picks a fresh seed.
+#[derive(Resource, Default)]
+pub struct SeedConfig {
+    pub seed: Option<u64>,
+}
+
+impl SeedConfig {
+    pub fn from_args_and_env() -> Result<SeedConfig, ArgError> {
+        if let Some(seed) = arg_value("--seed") {
+            return SeedConfig::from_value("--seed", &seed);
+        }
+        match std::env::var("BALL_GAME_SEED") {
+            Ok(seed) => SeedConfig::from_value("BALL_GAME_SEED", &seed),
+            Err(_) => Ok(SeedConfig::default()),
+        }
+    }
+
+    /// The seed given as `value` for the argument or environment variable `name`.
+    pub fn from_value(name: &'static str, value: &str) -> Result<SeedConfig, ArgError> {
+        match value.parse() {
+            Ok(seed) => Ok(SeedConfig { seed: Some(seed) }),
+            Err(_) => Err(ArgError::new(name, value, "an unsigned integer")),
+        }
+    }
+}
+
+/// The only source of randomness for gameplay, reseeded at the start of every run.
+#[derive(Resource, Clone, Deref, DerefMut)]
+pub struct GameRng {
+    pub seed: u64,
+    #[deref]
+    pub rng: ChaCha8Rng,
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> GameRng {
+        GameRng {
+            seed,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct StarSpawnTimer {
+    pub timer: Timer,
+}
+
+impl Default for StarSpawnTimer {
+    fn default() -> StarSpawnTimer {
+        StarSpawnTimer {
+            timer: Timer::from_seconds(STAR_SPAWN_TIME, TimerMode::Repeating),
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct SurvivalTime {
+    pub seconds: u32,
+    pub stopwatch: Stopwatch,
+}
+
+#[derive(Resource, Default)]
+pub struct EnemyCount {
+    pub value: usize,
+}
+
+#[derive(Component)]
+pub struct ScoreText;
+
+#[derive(Component)]
+pub struct SurvivalTimeText;
+
+#[derive(Component)]
+pub struct EnemyCountText;
+
+#[derive(Component)]
+pub struct LivesText;
+
+fn spawn_camera(mut commands: Commands) {
+    commands.spawn((Camera2d, arena::arena_projection(), SpatialListener::new(audio::LISTENER_EAR_GAP)));
+}
+
+/// Everything that belongs to a single run and is rebuilt on restart.
+pub type GameEntityFilter = Or<(With<Player>, With<Enemy>, With<Star>, With<PowerUp>)>;
+
+fn despawn_game_entities(mut commands: Commands, query: Query<Entity, GameEntityFilter>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn despawn_screen<T: Component>(mut commands: Commands, query: Query<Entity, With<T>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn reset_run(
+    mut score: ResMut<Score>,
+    mut survival_time: ResMut<SurvivalTime>,
+    mut star_spawn_timer: ResMut<StarSpawnTimer>,
+    seed_config: Res<SeedConfig>,
+    mut rng: ResMut<GameRng>,
+) {
+    *rng = GameRng::new(seed_config.seed.unwrap_or_else(random));
+    *score = Score::default();
+    *survival_time = SurvivalTime::default();
+    star_spawn_timer.timer.reset();
+}
+
+fn spawn_stars(
+    mut commands: Commands,
+    arena_bounds: Res<ArenaBounds>,
+    asset_server: Res<AssetServer>,
+    mut rng: ResMut<GameRng>,
+) {
+    for _ in 0..NUMBER_OF_STARS {
+        spawn_star(&mut commands, &mut **rng, &arena_bounds, &asset_server);
+    }
+}
+
+fn spawn_star(commands: &mut Commands, rng: &mut impl Rng, arena_bounds: &ArenaBounds, asset_server: &AssetServer) {
+    commands.spawn(star_bundle(asset_server, arena_bounds.random_position(rng, STAR_SIZE)));
+}
+
+pub(crate) fn star_bundle(asset_server: &AssetServer, position: Vec3) -> impl Bundle {
+    (
+        Sprite::from_image(
+            asset_server.load("sprites/star.png"),
+        ),
+        Transform::from_translation(position),
+        Star {},
+    )
+}
+
+/// Scores a star for the player touching it. When both players touch it, the first player gets it.
+fn player_hit_star(
+    mut commands: Commands,
+    player_query: Query<(&Player, &Transform, Option<&ActivePowerUps>)>,
+    star_query: Query<(Entity, &Transform), With<Star>>,
+    mut sfx_events: EventWriter<PlaySfx>,
+    mut particle_events: EventWriter<EmitParticles>,
+    mut score: ResMut<Score>,
+    game_config: Res<GameConfig>,
+) {
+    let mut players: Vec<_> = player_query.iter().collect();
+    players.sort_by_key(|(player, _, _)| player.index);
+    let mut collected = Vec::new();
+    for (player, player_transform, active_power_ups) in players {
+        let player_size = active_power_ups
+            .map_or(game_config.player_size, |active_power_ups| active_power_ups.player_size(&game_config));
+        for (star_entity, star_transform) in star_query.iter() {
+            if collected.contains(&star_entity) {
+                continue;
+            }
+            let distance = player_transform
+                .translation
+                .distance(star_transform.translation);
+            let player_radius = player_size / 2.0;
+            let star_radius = STAR_SIZE / 2.0;
+            if distance < player_radius + star_radius {
+                score.add(player.index, 1);
+                sfx_events.send(PlaySfx::centered(Sfx::StarPickup));
+                let position = star_transform.translation.truncate();
+                particle_events.send(EmitParticles::at(ParticleEffect::StarPickup, position));
+                commands.entity(star_entity).despawn();
+                collected.push(star_entity);
+            }
+        }
+    }
+}
+
+fn spawn_hud(mut commands: Commands) {
+    let font = TextFont {
+        font_size: 24.0,
+        ..default()
+    };
+
+    commands
+        .spawn(Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(10.0),
+            left: Val::Px(10.0),
+            flex_direction: FlexDirection::Column,
+            ..default()
+        })
+        .with_children(|parent| {
+            parent.spawn((Text::new("Score: 0"), font.clone(), ScoreText));
+            parent.spawn((Text::new("Time: 0s"), font.clone(), SurvivalTimeText));
+            parent.spawn((Text::new("Enemies: 0"), font.clone(), EnemyCountText));
+            parent.spawn((Text::new("Lives: 0"), font.clone(), LivesText));
+            parent.spawn((Text::new(""), font.clone(), powerup::PowerUpText));
+            parent.spawn((Text::new(""), font, audio::VolumeText));
+        });
+}
+
+fn tick_survival_time(
+    mut survival_time: ResMut<SurvivalTime>,
+    player_query: Query<(), With<Player>>,
+    time: Res<Time>,
+) {
+    if player_query.is_empty() {
+        return;
+    }
+
+    // Only mark the resource as changed when the seconds roll over, so the HUD text is not
+    // rewritten every frame.
+    survival_time.bypass_change_detection().stopwatch.tick(time.delta());
+    let seconds = survival_time.stopwatch.elapsed().as_secs() as u32;
+    if survival_time.seconds != seconds {
+        survival_time.seconds = seconds;
+    }
+}
+
+fn count_enemies(mut enemy_count: ResMut<EnemyCount>, enemy_query: Query<(), With<Enemy>>) {
+    let value = enemy_query.iter().count();
+    if enemy_count.value != value {
+        enemy_count.value = value;
+    }
+}
+
+fn update_score_text(
+    score: Res<Score>,
+    player_count: Res<PlayerCount>,
+    mut text_query: Query<&mut Text, With<ScoreText>>,
+) {
+    for mut text in text_query.iter_mut() {
+        **text = score_line(&score, *player_count);
+    }
+}
+
+/// `Score: 12`, with each player's share in co-op, such as `Score: 12  P1 7  P2 5`.
+fn score_line(score: &Score, player_count: PlayerCount) -> String {
+    let mut line = format!("Score: {}", score.value);
+    if player_count.is_coop() {
+        for (index, points) in score.players[..player_count.0].iter().enumerate() {
+            line.push_str(&format!("  P{} {}", index + 1, points));
+        }
+    }
+    line
+}
+
+fn update_survival_time_text(
+    survival_time: Res<SurvivalTime>,
+    mut text_query: Query<&mut Text, With<SurvivalTimeText>>,
+) {
+    for mut text in text_query.iter_mut() {
+        **text = format!("Time: {}s", survival_time.seconds);
+    }
+}
+
+fn update_enemy_count_text(
+    enemy_count: Res<EnemyCount>,
+    mut text_query: Query<&mut Text, With<EnemyCountText>>,
+) {
+    for mut text in text_query.iter_mut() {
+        **text = format!("Enemies: {}", enemy_count.value);
+    }
+}
+
+/// `Lives: 3`, or every player's lives in co-op, such as `Lives: P1 2  P2 0` once the second player
+/// is out.
+fn update_lives_text(
+    lives_query: Query<(&Player, &Lives)>,
+    player_count: Res<PlayerCount>,
+    mut text_query: Query<&mut Text, With<LivesText>>,
+) {
+    let mut lives = vec![0; player_count.0];
+    for (player, player_lives) in lives_query.iter() {
+        lives[player.index] = player_lives.remaining;
+    }
+    let line = if player_count.is_coop() {
+        let players: Vec<String> = lives
+            .iter()
+            .enumerate()
+            .map(|(index, remaining)| format!("P{} {}", index + 1, remaining))
+            .collect();
+        format!("Lives: {}", players.join("  "))
+    } else {
+        format!("Lives: {}", lives[0])
+    };
+    for mut text in text_query.iter_mut() {
+        // Only touch the text when the lives change, so the HUD is not laid out every frame.
+        if text.0 != line {
+            text.0 = line.clone();
+        }
+    }
+}
+
+fn tick_star_spawn_timer(mut star_spawn_timer: ResMut<StarSpawnTimer>, time: Res<Time>) {
+    star_spawn_timer.timer.tick(time.delta());
+}
+
+fn spawn_stars_over_time(
+    mut commands: Commands,
+    arena_bounds: Res<ArenaBounds>,
+    asset_server: Res<AssetServer>,
+    star_spawn_timer: Res<StarSpawnTimer>,
+    star_query: Query<(), With<Star>>,
+    mut rng: ResMut<GameRng>,
+) {
+    if star_spawn_timer.timer.finished() && star_query.iter().count() < MAX_STARS {
+        spawn_star(&mut commands, &mut **rng, &arena_bounds, &asset_server);
+    }
+}
+
+fn spawn_overlay<T: Component>(commands: &mut Commands, marker: T, lines: &[String]) {
+    commands
+        .spawn((
+            Node {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                row_gap: Val::Px(12.0),
+                ..default()
+            },
+            BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.6)),
+            marker,
+        ))
+        .with_children(|parent| {
+            for line in lines {
+                parent.spawn((
+                    Text::new(line.clone()),
+                    TextFont {
+                        font_size: 32.0,
+                        ..default()
+                    },
+                ));
+            }
+        });
+}
+
+fn spawn_main_menu(
+    mut commands: Commands,
+    high_scores: Res<HighScores>,
+    difficulty: Res<Difficulty>,
+    player_count: Res<PlayerCount>,
+    netplay: Option<Res<NetplaySession>>,
+) {
+    let mut lines = vec![
+        "Ball Game".to_string(),
+        format!("Difficulty: {:?}", *difficulty),
+        format!("Players: {}", player_count.0),
+        match netplay {
+            Some(session) => format!("Waiting for {}", session.peer()),
+            None => "Press Enter to start".to_string(),
+        },
+    ];
+    lines.extend(high_scores.table.lines());
+    spawn_overlay(&mut commands, MainMenuScreen, &lines);
+}
+
+fn spawn_pause_screen(mut commands: Commands) {
+    spawn_overlay(
+        &mut commands,
+        PauseScreen,
+        &["Paused".to_string(), "Press Escape to resume".to_string()],
+    );
+}
+
+fn spawn_game_over_screen(
+    mut commands: Commands,
+    score: Res<Score>,
+    player_count: Res<PlayerCount>,
+    high_scores: Res<HighScores>,
+    rng: Res<GameRng>,
+    netplay: Option<Res<NetplaySession>>,
+) {
+    let mut lines = vec![
+        "Game Over".to_string(),
+        score_line(&score, *player_count),
+        format!("Seed {}", rng.seed),
+        if netplay.is_some() {
+            "The online match is over".to_string()
+        } else {
+            "Press Enter to restart or Escape for the menu".to_string()
+        },
+    ];
+    lines.extend(high_scores.table.lines());
+    spawn_overlay(&mut commands, GameOverScreen, &lines);
+}
+
+fn start_game(action_state: Res<ActionState>, mut next_state: ResMut<NextState<GameState>>) {
+    if action_state.just_pressed(Action::Confirm) {
+        next_state.set(GameState::InGame);
+    }
+}
+
+fn toggle_pause(
+    action_state: Res<ActionState>,
+    state: Res<State<GameState>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if action_state.just_pressed(Action::Pause) {
+        match state.get() {
+            GameState::InGame => next_state.set(GameState::Paused),
+            GameState::Paused => next_state.set(GameState::InGame),
+            _ => {}
+        }
+    }
+}
+
+fn restart_game(action_state: Res<ActionState>, mut next_state: ResMut<NextState<GameState>>) {
+    if action_state.just_pressed(Action::Confirm) {
+        next_state.set(GameState::InGame);
+    } else if action_state.just_pressed(Action::Back) {
+        next_state.set(GameState::MainMenu);
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -1,257 +1,54 @@
 use bevy::prelude::*;
-use bevy::window::PrimaryWindow;
-use rand::prelude::*;
+use rand::random;
 
-pub const PLAYER_SPEED: f32 = 500.0;
-pub const PLAYER_SIZE: f32 = 64.0;
-pub const NUMBER_OF_ENEMIES: usize = 4;
-pub const ENEMY_SIZE: f32 = 64.0;
-pub const ENEMY_SPEED: f32 = 200.0;
+use ball_game::audio::Volumes;
+use ball_game::config::Difficulty;
+use ball_game::high_scores::HighScores;
+use ball_game::input::InputMap;
+use ball_game::netplay::NetplaySession;
+use ball_game::replay::ReplayMode;
+use ball_game::{ArgError, GamePlugin, GameRng, PlayerCount, SeedConfig};
 
 fn main() {
-    App::new()
+    let mut seed_config = or_exit(SeedConfig::from_args_and_env());
+    let mut difficulty = or_exit(Difficulty::from_args());
+    let mut player_count = or_exit(PlayerCount::from_args());
+    let replay_mode = or_exit(ReplayMode::from_args());
+    if let ReplayMode::Playback { replay, .. } = &replay_mode {
+        // A replay only makes sense with the seed, difficulty and players it was recorded with.
+        seed_config.seed = Some(replay.seed);
+        difficulty = replay.difficulty;
+        player_count = PlayerCount(replay.players);
+    }
+    let netplay = or_exit(NetplaySession::from_args(&replay_mode));
+    if netplay.is_some() {
+        // Both players are in every online match, the handshake agrees on the seed and difficulty.
+        player_count = PlayerCount(2);
+    }
+    let game_rng = GameRng::new(seed_config.seed.unwrap_or_else(random));
+
+    let mut app = App::new();
+    app
         .add_plugins(DefaultPlugins)
-        .add_systems(Startup, (spawn_camera, spawn_player, spawn_enemies).chain())
-        .add_systems(Update, player_movement)
-        .add_systems(Update, confine_player_movement)
-        .add_systems(Update, enemy_movement)
-        .add_systems(Update, (update_enemy_direction, enemy_wall_collision).chain())
-        .add_systems(Update, confine_enemy_movement)
-        .add_systems(Update, enemy_hit_player)
-        .add_event::<EnemyWallCollisionEvent>()
-        .run();
-}
-
-#[derive(Component)]
-struct Player {}
-
-#[derive(Component)]
-struct Enemy {
-    pub direction: Vec2,
-}
-
-#[derive(Event, Default)]
-struct EnemyWallCollisionEvent;
-
-#[derive(Resource, Deref)]
-struct EnemyWallCollisionSound(Handle<AudioSource>);
-
-fn spawn_player(
-    mut commands: Commands,
-    asset_server: Res<AssetServer>,
-) {
-    commands.spawn((
-      Sprite::from_image(
-            asset_server.load("sprites/ball_blue_large.png"),
-        ),
-        Player {},
-    ));
-}
-
-fn spawn_camera(mut commands: Commands) {
-    commands.spawn(
-      Camera2d
-    );
-}
-
-fn spawn_enemies(
-    mut commands: Commands,
-    windows: Query<&Window>,
-    asset_server: Res<AssetServer>,
-) {
-    let window = windows.single();
-
-    let half_enemy_size = ENEMY_SIZE / 2.0;
-    for _ in 0..NUMBER_OF_ENEMIES {
-        let random_x = random::<f32>() * (window.width() - ENEMY_SIZE) - window.width() / 2.0 + half_enemy_size;
-        let random_y = random::<f32>() * (window.height() - ENEMY_SIZE) - window.height() / 2.0 + half_enemy_size;
-
-        commands.spawn((
-            Sprite::from_image(
-              asset_server.load("sprites/ball_red_large.png"),
-            ),
-            Transform::from_xyz(random_x, random_y, 0.0),
-            Enemy {
-              direction: Vec2::new(random::<f32>(), random::<f32>()).normalize(),
-            },
-        ));
-    }
-
-    let wall_collision_sound = asset_server.load("audio/pluck_001.ogg");
-    commands.insert_resource(EnemyWallCollisionSound(wall_collision_sound));
-}
-
-fn player_movement(
-    keyboard_input: Res<ButtonInput<KeyCode>>,
-    mut player_query: Query<&mut Transform, With<Player>>,
-    time: Res<Time>,
-) {
-    if let Ok(mut transform) = player_query.get_single_mut() {
-        let mut direction = Vec3::ZERO;
-
-        if keyboard_input.pressed(KeyCode::ArrowLeft) || keyboard_input.pressed(KeyCode::KeyA) {
-            direction += Vec3::new(-1.0, 0.0, 0.0);
-        }
-        if keyboard_input.pressed(KeyCode::ArrowRight) || keyboard_input.pressed(KeyCode::KeyD) {
-            direction += Vec3::new(1.0, 0.0, 0.0);
-        }
-        if keyboard_input.pressed(KeyCode::ArrowUp) || keyboard_input.pressed(KeyCode::KeyW) {
-            direction += Vec3::new(0.0, 1.0, 0.0);
-        }
-        if keyboard_input.pressed(KeyCode::ArrowDown) || keyboard_input.pressed(KeyCode::KeyS) {
-            direction += Vec3::new(0.0, -1.0, 0.0);
-        }
-
-        if direction.length() > 0.0 {
-            direction = direction.normalize();
-        }
-
-        transform.translation += direction * PLAYER_SPEED * time.delta().as_secs_f32();
-    }
-}
-
-fn confine_player_movement(
-    mut player_query: Query<&mut Transform, With<Player>>,
-    windows: Query<&Window>,
-) {
-    if let Ok(mut player_transform) = player_query.get_single_mut() {
-        let window = windows.single();
-
-        let half_player_size = PLAYER_SIZE / 2.0;
-        let x_min = - window.width() / 2.0 + half_player_size;
-        let x_max = window.width() / 2.0 - half_player_size;
-        let y_min = - window.height() / 2.0 + half_player_size;
-        let y_max = window.height() / 2.0 - half_player_size;
-
-        let mut translation = player_transform.translation;
-
-
-        if translation.x < x_min {
-            translation.x = x_min;
-        } else if translation.x > x_max {
-            translation.x = x_max;
-        }
-        if translation.y < y_min {
-            translation.y = y_min;
-        } else if translation.y > y_max {
-            translation.y = y_max;
-        }
-
-        player_transform.translation = translation;
-    }
-}
-
-
-fn enemy_movement(mut enemy_query: Query<(&mut Transform, &Enemy)>, time: Res<Time>) {
-    for (mut transform, enemy) in enemy_query.iter_mut() {
-        let direction = Vec3::new(enemy.direction.x, enemy.direction.y, 0.0);
-        transform.translation += direction * ENEMY_SPEED * time.delta().as_secs_f32();
-    }
-}
-
-fn update_enemy_direction(
-    mut enemy_query: Query<(&Transform, &mut Enemy)>,
-    window_query: Query<&Window, With<PrimaryWindow>>,
-    mut collision_events: EventWriter<EnemyWallCollisionEvent>,
-) {
-    let window = window_query.get_single().unwrap();
-
-    let half_enemy_size = ENEMY_SIZE / 2.0;
-    let x_min = - window.width() / 2.0 + half_enemy_size;
-    let x_max = window.width() / 2.0 - half_enemy_size;
-    let y_min = - window.height() / 2.0 + half_enemy_size;
-    let y_max = window.height() / 2.0 - half_enemy_size;
-
-    for (transform, mut enemy) in enemy_query.iter_mut() {
-        let mut direction_changed = false;
-
-        let translation = transform.translation;
-
-        // Sometimes, enemys get stuck on an edge
-        if translation.x > x_max {
-            println!("edge! x_max: {} x {} direction {}", x_max, translation.x, enemy.direction.x);
-        }
-        if translation.y > y_max {
-            println!("egdge! y_max: {} y {} direction {}", y_max, translation.y, enemy.direction.y);
-        }
-
-        if translation.x < x_min || translation.x > x_max {
-            enemy.direction.x = - enemy.direction.x;
-            direction_changed = true;
-        }
-
-        if translation.y < y_min || translation.y > y_max {
-            enemy.direction.y = - enemy.direction.y;
-            direction_changed = true;
-        }
-
-        if direction_changed {
-            collision_events.send_default();
-        }
-    }
-}
-
-fn enemy_wall_collision(
-    mut commands: Commands,
-    mut collision_events: EventReader<EnemyWallCollisionEvent>,
-    sound: Res<EnemyWallCollisionSound>,
-) {
-    if !collision_events.is_empty() {
-        // This prevents events staying active on the next frame.
-        collision_events.clear();
-        commands.spawn((AudioPlayer(sound.clone()), PlaybackSettings::DESPAWN));
-    }
-}
-
-fn confine_enemy_movement(
-    mut enemy_query: Query<(&mut Transform, &Enemy), With<Enemy>>,
-    window_query: Query<&Window, With<PrimaryWindow>>,
-) {
-    let window = window_query.get_single().unwrap();
-
-    let half_enemy_size = ENEMY_SIZE / 2.0;
-    let x_min = - window.width() / 2.0 + half_enemy_size;
-    let x_max = window.width() / 2.0 - half_enemy_size;
-    let y_min = - window.height() / 2.0 + half_enemy_size;
-    let y_max = window.height() / 2.0 - half_enemy_size;
-
-    for (mut transform, enemy) in enemy_query.iter_mut() {
-        let mut translation = transform.translation;
-
-        if translation.x < x_min {
-            translation.x = x_min + enemy.direction.x.abs();
-        } else if translation.x > x_max {
-            translation.x = x_max -  enemy.direction.x.abs();
-        }
-        if translation.y < y_min {
-            translation.y = y_min + enemy.direction.y.abs();
-        } else if translation.y > y_max {
-            translation.y = y_max - enemy.direction.y.abs();
-        }
-
-        transform.translation = translation;
-    }
-}
-
-fn enemy_hit_player(
-    mut commands: Commands,
-    mut player_query: Query<(Entity, &Transform), With<Player>>,
-    enemy_query: Query<&Transform, With<Enemy>>,
-    asset_server: Res<AssetServer>,
-) {
-    if let Ok((player_entity, player_transform)) = player_query.get_single_mut() {
-        for enemy_transform in enemy_query.iter() {
-            let distance = player_transform
-                .translation
-                .distance(enemy_transform.translation);
-            let player_radius = PLAYER_SIZE / 2.0;
-            let enemy_radius = ENEMY_SIZE / 2.0;
-            if distance < player_radius + enemy_radius {
-                println!("Enemy hit player! Game Over!");
-                let sound_effect = asset_server.load("audio/explosionCrunch_000.ogg");
-                AudioPlayer::<AudioSource>(sound_effect);
-                commands.entity(player_entity).despawn();
-            }
-        }
-    }
+        .insert_resource(seed_config)
+        .insert_resource(game_rng)
+        .insert_resource(difficulty)
+        .insert_resource(player_count)
+        .insert_resource(replay_mode)
+        .insert_resource(HighScores::load(HighScores::default_path()))
+        .insert_resource(InputMap::load(InputMap::default_path().as_deref()))
+        .insert_resource(Volumes::load(Volumes::default_path()))
+        .add_plugins(GamePlugin);
+    if let Some(session) = netplay {
+        app.insert_resource(session);
+    }
+    app.run();
+}
+
+/// Stops with the message instead of a panic when an argument has a value the game does not take.
+fn or_exit<T>(result: Result<T, ArgError>) -> T {
+    result.unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        std::process::exit(2);
+    })
 }
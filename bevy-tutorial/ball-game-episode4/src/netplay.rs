@@ -0,0 +1,794 @@
+//! Online co-op for two instances of the game over UDP, with GGRS-style rollback. There is no
+//! versus mode, both players always play on the same side.
+//!
+//! Start two instances that name each other, for example on one machine:
+//!
+//! ```text
+//! cargo run -- --bind 127.0.0.1:7000 --peer 127.0.0.1:7001 --local-player 0
+//! cargo run -- --bind 127.0.0.1:7001 --peer 127.0.0.1:7000 --local-player 1
+//! ```
+//!
+//! Every fixed tick each instance sends the input of its own player and carries on straight away,
+//! predicting that the other player still does what they last did. The state at the start of every
+//! tick that is not confirmed yet is kept as a `GameSnapshot`. When the real input of a predicted
+//! tick arrives and differs, the game is restored to that tick and simulated forward again. An
+//! instance more than `MAX_PREDICTION` ticks ahead of the other player's input waits for it.
+//!
+//! Once a tick is confirmed, both instances compare the checksums of its state and report the first
+//! mismatch as a desync. The handshake also compares the checksums of the game configs, and only
+//! starts the match once both play with the same tunables.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use bevy::app::{FixedMain, RunFixedMainLoop, RunFixedMainLoopSystem};
+use bevy::prelude::*;
+use rand::random;
+
+use crate::audio::PlaySfx;
+use crate::config::{Difficulty, GameConfig};
+use crate::enemy::EnemyWallCollisionEvent;
+use crate::input::ActionState;
+use crate::particles::EmitParticles;
+use crate::player::MAX_PLAYERS;
+use crate::replay::ReplayMode;
+use crate::snapshot::GameSnapshot;
+use crate::{arg_value, ArgError, GameState, Player, PlayerInput, SeedConfig};
+
+/// How many ticks an instance may run ahead of the input it has from the other player.
+pub const MAX_PREDICTION: u32 = 8;
+/// The most inputs a packet carries, the oldest the other player has not received first.
+pub const MAX_INPUTS_PER_PACKET: usize = 32;
+/// How many of the latest confirmed checksums every packet carries.
+pub const CHECKSUMS_PER_PACKET: usize = 4;
+/// How many ticks of checksums are kept to compare with ones that arrive late.
+pub const CHECKSUM_HISTORY: u32 = 256;
+/// How long the other player may stay silent before the match is given up.
+pub const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Exchanges input with the other instance, rolls back mispredicted ticks and compares checksums,
+/// while a `NetplaySession` exists.
+pub struct NetplayPlugin;
+
+impl Plugin for NetplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            RunFixedMainLoop,
+            poll_netplay
+                .run_if(is_online)
+                .in_set(RunFixedMainLoopSystem::BeforeFixedMainLoop),
+        )
+        .add_systems(FixedPostUpdate, hold_game_over_until_confirmed.run_if(is_online));
+    }
+}
+
+/// The first tick whose checksums differed between the two instances.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Desync {
+    pub frame: u32,
+    pub local: u64,
+    pub remote: u64,
+}
+
+/// A match with another instance of the game, from the handshake to the end of the run.
+#[derive(Resource)]
+pub struct NetplaySession {
+    socket: UdpSocket,
+    peer: SocketAddr,
+    /// The player this instance controls. The first player's seed and difficulty are played.
+    pub local_player: usize,
+    /// The seed offered when `SeedConfig` has none.
+    fallback_seed: u64,
+    hello: Option<Hello>,
+    peer_hello: Option<Hello>,
+    /// Whether the other instance was last heard offering a different game config.
+    config_mismatch: bool,
+    peer_started: bool,
+    running: bool,
+    /// The next tick to simulate.
+    frame: u32,
+    local_inputs: Vec<PlayerInput>,
+    /// The other player's input, confirmed for every tick up to its length.
+    remote_inputs: Vec<PlayerInput>,
+    /// The input guessed for the other player on ticks simulated before theirs arrived.
+    predicted: BTreeMap<u32, PlayerInput>,
+    /// How many of our inputs the other player has received.
+    peer_ack: usize,
+    first_incorrect: Option<u32>,
+    resimulating: bool,
+    snapshots: BTreeMap<u32, GameSnapshot>,
+    checksums: BTreeMap<u32, u64>,
+    remote_checksums: BTreeMap<u32, u64>,
+    /// The tick the last player went out on, while it may still be rolled back.
+    game_over_frame: Option<u32>,
+    /// The run is over, confirmed or because the other player went silent.
+    ended: bool,
+    last_received: Instant,
+    disconnected: bool,
+    pub rollbacks: u32,
+    /// How many ticks had their checksums compared with the other instance.
+    pub checked_frames: u32,
+    pub desync: Option<Desync>,
+}
+
+impl NetplaySession {
+    /// Plays against `peer` on an open `socket`.
+    pub fn new(socket: UdpSocket, peer: SocketAddr, local_player: usize) -> io::Result<NetplaySession> {
+        assert!(local_player < MAX_PLAYERS, "the local player must be 0 or 1");
+        socket.set_nonblocking(true)?;
+        Ok(NetplaySession {
+            socket,
+            peer,
+            local_player,
+            fallback_seed: random(),
+            hello: None,
+            peer_hello: None,
+            config_mismatch: false,
+            peer_started: false,
+            running: false,
+            frame: 0,
+            local_inputs: Vec::new(),
+            remote_inputs: Vec::new(),
+            predicted: BTreeMap::new(),
+            peer_ack: 0,
+            first_incorrect: None,
+            resimulating: false,
+            snapshots: BTreeMap::new(),
+            checksums: BTreeMap::new(),
+            remote_checksums: BTreeMap::new(),
+            game_over_frame: None,
+            ended: false,
+            last_received: Instant::now(),
+            disconnected: false,
+            rollbacks: 0,
+            checked_frames: 0,
+            desync: None,
+        })
+    }
+
+    /// The session asked for with `--bind <address> --peer <address> --local-player <0 or 1>`, if
+    /// any. An online match cannot be recorded or played back, so `replay_mode` must be off.
+    pub fn from_args(replay_mode: &ReplayMode) -> Result<Option<NetplaySession>, ArgError> {
+        NetplaySession::from_arg(
+            arg_value("--peer").as_deref(),
+            arg_value("--bind").as_deref(),
+            arg_value("--local-player").as_deref(),
+            replay_mode,
+        )
+    }
+
+    pub fn from_arg(
+        peer: Option<&str>,
+        bind: Option<&str>,
+        local_player: Option<&str>,
+        replay_mode: &ReplayMode,
+    ) -> Result<Option<NetplaySession>, ArgError> {
+        let Some(peer) = peer else {
+            return Ok(None);
+        };
+        if !matches!(replay_mode, ReplayMode::Off) {
+            return Err(ArgError::new("--peer", peer, "left out when recording or playing back a replay"));
+        }
+        let local_player = match local_player {
+            None | Some("0") => 0,
+            Some("1") => 1,
+            Some(other) => return Err(ArgError::new("--local-player", other, "0 or 1")),
+        };
+        let peer_address: SocketAddr = peer
+            .parse()
+            .map_err(|_| ArgError::new("--peer", peer, "an address such as 127.0.0.1:7001"))?;
+        let bind = bind.unwrap_or("0.0.0.0:0");
+        let session = UdpSocket::bind(bind)
+            .and_then(|socket| NetplaySession::new(socket, peer_address, local_player))
+            .map_err(|error| ArgError::new("--bind", bind, format!("an address to listen on ({})", error)))?;
+        Ok(Some(session))
+    }
+
+    pub fn peer(&self) -> SocketAddr {
+        self.peer
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    /// The checksum of the state at the start of `frame`, as simulated so far.
+    pub fn checksum(&self, frame: u32) -> Option<u64> {
+        self.checksums.get(&frame).copied()
+    }
+
+    fn remote_player(&self) -> usize {
+        1 - self.local_player
+    }
+
+    fn confirmed_frames(&self) -> u32 {
+        self.remote_inputs.len() as u32
+    }
+
+    /// The last tick whose state no longer depends on a prediction, once there is one.
+    fn last_final_frame(&self) -> Option<u32> {
+        self.confirmed_frames().min(self.frame).checked_sub(1)
+    }
+
+    fn send(&self, packet: &Packet) {
+        // Lost packets are made up for by the next ones, which repeat everything not yet received.
+        let _ = self.socket.send_to(&packet.encode(), self.peer);
+    }
+
+    fn receive(&mut self) {
+        let mut buffer = [0; 1500];
+        loop {
+            let (length, from) = match self.socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => break,
+                Err(_) => break,
+            };
+            if from != self.peer {
+                continue;
+            }
+            let Some(packet) = Packet::decode(&buffer[..length]) else {
+                continue;
+            };
+            self.last_received = Instant::now();
+            match packet {
+                Packet::Hello(hello) => {
+                    self.peer_hello = Some(hello);
+                    // The other instance has not heard that we heard it yet.
+                    if let Some(hello) = self.hello.filter(|_| self.running) {
+                        self.send(&Packet::Hello(hello));
+                    }
+                }
+                Packet::Inputs {
+                    ack,
+                    start,
+                    inputs,
+                    checksums,
+                } => {
+                    self.peer_started = true;
+                    self.peer_ack = self.peer_ack.max(ack as usize);
+                    self.receive_inputs(start, &inputs);
+                    self.remote_checksums.extend(checksums);
+                }
+            }
+        }
+    }
+
+    fn receive_inputs(&mut self, start: u32, inputs: &[PlayerInput]) {
+        for (frame, &input) in (start..).zip(inputs) {
+            // Only take the next input in order, the rest will be sent again.
+            if frame != self.confirmed_frames() {
+                continue;
+            }
+            self.remote_inputs.push(input);
+            if self.predicted.remove(&frame).is_some_and(|predicted| predicted != input) {
+                self.first_incorrect = Some(self.first_incorrect.map_or(frame, |first| first.min(frame)));
+            }
+        }
+    }
+
+    /// Sends the hello until the other instance has heard it, then starts the run with the
+    /// settings of the first player. Waits for as long as the two game configs differ.
+    fn handshake(&mut self, seed: Option<u64>, difficulty: Difficulty, config: u64) -> Handshake {
+        let hello = Hello {
+            seed: seed.unwrap_or(self.fallback_seed),
+            difficulty,
+            config,
+            player: self.local_player as u8,
+            heard: self.peer_hello.is_some(),
+        };
+        self.hello = Some(hello);
+        self.send(&Packet::Hello(hello));
+
+        let Some(peer_hello) = self.peer_hello else {
+            return Handshake::Waiting;
+        };
+        if peer_hello.player as usize == self.local_player {
+            return Handshake::SameSeat;
+        }
+        let config_mismatch = peer_hello.config != config;
+        if config_mismatch && !self.config_mismatch {
+            eprintln!("{} plays with a different game config, waiting until both are the same", self.peer);
+        }
+        self.config_mismatch = config_mismatch;
+        if config_mismatch {
+            return Handshake::Waiting;
+        }
+        if !peer_hello.heard && !self.peer_started {
+            return Handshake::Waiting;
+        }
+        self.running = true;
+        self.last_received = Instant::now();
+        Handshake::Started(if self.local_player == 0 { hello } else { peer_hello })
+    }
+
+    /// The input of every player on `frame`, predicting the other player's while it has not
+    /// arrived.
+    fn inputs(&mut self, frame: u32) -> [PlayerInput; MAX_PLAYERS] {
+        let remote = match self.remote_inputs.get(frame as usize) {
+            Some(&input) => input,
+            None => {
+                let predicted = self.remote_inputs.last().copied().unwrap_or_default();
+                self.predicted.insert(frame, predicted);
+                predicted
+            }
+        };
+        let mut inputs = [PlayerInput::default(); MAX_PLAYERS];
+        inputs[self.local_player] = self.local_inputs[frame as usize];
+        inputs[self.remote_player()] = remote;
+        inputs
+    }
+
+    fn send_inputs(&self) {
+        let start = self.peer_ack.min(self.local_inputs.len());
+        let end = (start + MAX_INPUTS_PER_PACKET).min(self.local_inputs.len());
+        let checksums = match self.last_final_frame() {
+            Some(last) => self
+                .checksums
+                .range(..=last)
+                .rev()
+                .take(CHECKSUMS_PER_PACKET)
+                .map(|(&frame, &checksum)| (frame, checksum))
+                .collect(),
+            None => Vec::new(),
+        };
+        self.send(&Packet::Inputs {
+            ack: self.confirmed_frames(),
+            start: start as u32,
+            inputs: self.local_inputs[start..end].to_vec(),
+            checksums,
+        });
+    }
+
+    fn compare_checksums(&mut self) {
+        let Some(last) = self.last_final_frame() else {
+            return;
+        };
+        let remote_checksums = self.remote_checksums.split_off(&(last + 1));
+        for (frame, remote) in std::mem::replace(&mut self.remote_checksums, remote_checksums) {
+            let Some(&local) = self.checksums.get(&frame) else {
+                continue;
+            };
+            self.checked_frames += 1;
+            if local != remote && self.desync.is_none() {
+                eprintln!("Desync on tick {}: checksum {:016x} here, {:016x} on {}", frame, local, remote, self.peer);
+                self.desync = Some(Desync { frame, local, remote });
+            }
+        }
+    }
+
+    /// Forgets the snapshots that can no longer be rolled back to and old checksums.
+    fn prune(&mut self) {
+        let oldest_rollback = self.confirmed_frames().min(self.frame);
+        self.snapshots.retain(|&frame, _| frame >= oldest_rollback);
+        if let Some(last) = self.last_final_frame() {
+            let oldest_checksum = last.saturating_sub(CHECKSUM_HISTORY);
+            self.checksums.retain(|&frame, _| frame >= oldest_checksum);
+        }
+    }
+}
+
+/// Whether this instance plays online.
+pub fn is_online(session: Option<Res<NetplaySession>>) -> bool {
+    session.is_some()
+}
+
+/// Whether the next tick may be simulated: always offline, online only while it is at most
+/// `MAX_PREDICTION` ticks ahead of the other player's input.
+pub fn within_prediction_window(session: Option<Res<NetplaySession>>) -> bool {
+    session.is_none_or(|session| session.frame < session.confirmed_frames() + MAX_PREDICTION)
+}
+
+/// Exchanges packets with the other instance once a frame: starts the run after the handshake,
+/// then rolls back mispredicted ticks, compares checksums and ends the run with the last player.
+pub fn poll_netplay(world: &mut World) {
+    let seed = world.resource::<SeedConfig>().seed;
+    let difficulty = *world.resource::<Difficulty>();
+    let running = world.resource::<NetplaySession>().running;
+    let config = if running { 0 } else { world.resource::<GameConfig>().checksum() };
+    let mut session = world.resource_mut::<NetplaySession>();
+    session.receive();
+
+    if !running {
+        match session.handshake(seed, difficulty, config) {
+            Handshake::Waiting => {}
+            Handshake::Started(settings) => {
+                eprintln!("Connected to {} as player {}", session.peer, session.local_player + 1);
+                world.resource_mut::<SeedConfig>().seed = Some(settings.seed);
+                world.insert_resource(settings.difficulty);
+                world.resource_mut::<NextState<GameState>>().set(GameState::InGame);
+            }
+            Handshake::SameSeat => {
+                eprintln!(
+                    "{} is player {} as well, pass a different --local-player to each instance",
+                    session.peer,
+                    session.local_player + 1
+                );
+                world.send_event(AppExit::from_code(2));
+            }
+        }
+        return;
+    }
+
+    if let Some(frame) = session.first_incorrect.take() {
+        roll_back(world, frame);
+    }
+
+    let mut session = world.resource_mut::<NetplaySession>();
+    session.compare_checksums();
+    session.send_inputs();
+    session.prune();
+
+    let confirmed_game_over = session
+        .game_over_frame
+        .is_some_and(|frame| frame < session.confirmed_frames());
+    let timed_out = !session.disconnected && session.last_received.elapsed() > DISCONNECT_TIMEOUT;
+    if timed_out {
+        eprintln!("Lost the connection to {}", session.peer);
+        session.disconnected = true;
+    }
+    if confirmed_game_over {
+        session.game_over_frame = None;
+    }
+    if confirmed_game_over || timed_out {
+        session.ended = true;
+        world.resource_mut::<NextState<GameState>>().set(GameState::GameOver);
+    }
+}
+
+/// Restores the snapshot of `frame` and simulates forward again to the current tick, now with the
+/// other player's real input.
+fn roll_back(world: &mut World, frame: u32) {
+    let mut session = world.resource_mut::<NetplaySession>();
+    let Some(snapshot) = session.snapshots.remove(&frame) else {
+        // The mispredicted tick can no longer be corrected, so the two instances will drift apart.
+        eprintln!("Cannot roll back to tick {}, it is no longer kept, ending the match", frame);
+        session.ended = true;
+        world.resource_mut::<NextState<GameState>>().set(GameState::GameOver);
+        return;
+    };
+    let target = session.frame;
+    session.frame = frame;
+    session.resimulating = true;
+    session.rollbacks += 1;
+    if session.game_over_frame.is_some_and(|game_over_frame| game_over_frame >= frame) {
+        session.game_over_frame = None;
+    }
+
+    snapshot.restore(world);
+    *world.resource_mut::<Time>() = world.resource::<Time<Fixed>>().as_generic();
+    for _ in frame..target {
+        world.run_schedule(FixedMain);
+    }
+    *world.resource_mut::<Time>() = world.resource::<Time<Virtual>>().as_generic();
+    world.resource_mut::<NetplaySession>().resimulating = false;
+
+    // The sounds and particles of these ticks already played the first time around.
+    world.resource_mut::<Events<PlaySfx>>().clear();
+    world.resource_mut::<Events<EmitParticles>>().clear();
+    world.resource_mut::<Events<EnemyWallCollisionEvent>>().clear();
+}
+
+/// Starts a tick online: keeps a snapshot of the state to roll back to, reads the local player's
+/// input and hands every player their input for the tick.
+pub fn advance_frame(world: &mut World) {
+    let snapshot = GameSnapshot::save(world);
+    let local_input = read_local_input(world);
+
+    let mut session = world.resource_mut::<NetplaySession>();
+    let frame = session.frame;
+    session.checksums.insert(frame, snapshot.checksum());
+    session.snapshots.insert(frame, snapshot);
+    // While simulating again, the local input of the tick is already known.
+    if frame as usize == session.local_inputs.len() {
+        session.local_inputs.push(local_input);
+    }
+    let inputs = session.inputs(frame);
+    session.frame += 1;
+    if !session.resimulating {
+        session.send_inputs();
+    }
+
+    for (player, mut player_input) in world.query::<(&Player, &mut PlayerInput)>().iter_mut(world) {
+        *player_input = inputs[player.index];
+    }
+}
+
+/// Every local device drives the local player, aiming with the mouse as the first player would.
+fn read_local_input(world: &mut World) -> PlayerInput {
+    let local_player = world.resource::<NetplaySession>().local_player;
+    let position = world
+        .query::<(&Player, &Transform)>()
+        .iter(world)
+        .find(|(player, _)| player.index == local_player)
+        .map(|(_, transform)| transform.translation.truncate());
+    let action_state = world.resource::<ActionState>();
+    let seat = &action_state.seats[0];
+    let input = PlayerInput::from_movement(seat.movement());
+    match position.and_then(|position| seat.fire_direction(position, action_state.cursor)) {
+        Some(direction) => input.with_fire(direction),
+        None => input,
+    }
+}
+
+/// Holds back the game over of the last player going out until the tick is confirmed, as a
+/// rollback may yet save them.
+fn hold_game_over_until_confirmed(
+    mut session: ResMut<NetplaySession>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if !session.ended && matches!(*next_state, NextState::Pending(GameState::GameOver)) {
+        next_state.reset();
+        let frame = session.frame.saturating_sub(1);
+        session.game_over_frame.get_or_insert(frame);
+    }
+}
+
+/// How far the handshake has got.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Handshake {
+    Waiting,
+    /// Both instances are ready, with the settings of the run.
+    Started(Hello),
+    /// The other instance plays the same player, so the match can never start.
+    SameSeat,
+}
+
+/// The settings one instance offers the other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Hello {
+    seed: u64,
+    difficulty: Difficulty,
+    /// The checksum of the sender's `GameConfig`.
+    config: u64,
+    player: u8,
+    /// Whether this instance has heard the other's hello.
+    heard: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Packet {
+    Hello(Hello),
+    /// The sender's inputs from tick `start` on, how many of the receiver's inputs it has, and the
+    /// checksums of its latest confirmed ticks.
+    Inputs {
+        ack: u32,
+        start: u32,
+        inputs: Vec<PlayerInput>,
+        checksums: Vec<(u32, u64)>,
+    },
+}
+
+const HELLO: u8 = 0;
+const INPUTS: u8 = 1;
+
+impl Packet {
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        match self {
+            Packet::Hello(hello) => {
+                bytes.push(HELLO);
+                bytes.extend(hello.seed.to_le_bytes());
+                bytes.push(match hello.difficulty {
+                    Difficulty::Easy => 0,
+                    Difficulty::Normal => 1,
+                    Difficulty::Hard => 2,
+                });
+                bytes.extend(hello.config.to_le_bytes());
+                bytes.push(hello.player);
+                bytes.push(hello.heard as u8);
+            }
+            Packet::Inputs {
+                ack,
+                start,
+                inputs,
+                checksums,
+            } => {
+                bytes.push(INPUTS);
+                bytes.extend(ack.to_le_bytes());
+                bytes.extend(start.to_le_bytes());
+                bytes.push(inputs.len() as u8);
+                for input in inputs {
+                    bytes.extend([input.x, input.y, input.aim_x, input.aim_y].map(|value| value as u8));
+                }
+                bytes.push(checksums.len() as u8);
+                for (frame, checksum) in checksums {
+                    bytes.extend(frame.to_le_bytes());
+                    bytes.extend(checksum.to_le_bytes());
+                }
+            }
+        }
+        bytes
+    }
+
+    /// `None` for anything that is not a whole packet.
+    fn decode(bytes: &[u8]) -> Option<Packet> {
+        let mut reader = Reader(bytes);
+        let packet = match reader.byte()? {
+            HELLO => Packet::Hello(Hello {
+                seed: u64::from_le_bytes(reader.take()?),
+                difficulty: match reader.byte()? {
+                    0 => Difficulty::Easy,
+                    1 => Difficulty::Normal,
+                    2 => Difficulty::Hard,
+                    _ => return None,
+                },
+                config: u64::from_le_bytes(reader.take()?),
+                player: reader.byte()?,
+                heard: reader.byte()? != 0,
+            }),
+            INPUTS => {
+                let ack = u32::from_le_bytes(reader.take()?);
+                let start = u32::from_le_bytes(reader.take()?);
+                let inputs = (0..reader.byte()?)
+                    .map(|_| {
+                        let [x, y, aim_x, aim_y] = reader.take::<4>()?.map(|value| value as i8);
+                        Some(PlayerInput { x, y, aim_x, aim_y })
+                    })
+                    .collect::<Option<_>>()?;
+                let checksums = (0..reader.byte()?)
+                    .map(|_| Some((u32::from_le_bytes(reader.take()?), u64::from_le_bytes(reader.take()?))))
+                    .collect::<Option<_>>()?;
+                Packet::Inputs {
+                    ack,
+                    start,
+                    inputs,
+                    checksums,
+                }
+            }
+            _ => return None,
+        };
+        reader.0.is_empty().then_some(packet)
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let (bytes, rest) = self.0.split_first_chunk::<N>()?;
+        self.0 = rest;
+        Some(*bytes)
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        self.take::<1>().map(|[byte]| byte)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packets_round_trip() {
+        let packets = [
+            Packet::Hello(Hello {
+                seed: 0x0123_4567_89ab_cdef,
+                difficulty: Difficulty::Hard,
+                config: 0xfedc_ba98_7654_3210,
+                player: 1,
+                heard: true,
+            }),
+            Packet::Inputs {
+                ack: 7,
+                start: 3,
+                inputs: vec![PlayerInput::from_movement(Vec2::NEG_X).with_fire(Vec2::Y), PlayerInput::default()],
+                checksums: vec![(2, u64::MAX), (1, 42)],
+            },
+        ];
+        for packet in packets {
+            assert_eq!(Packet::decode(&packet.encode()), Some(packet));
+        }
+    }
+
+    #[test]
+    fn truncated_or_padded_packets_are_ignored() {
+        let bytes = Packet::Inputs {
+            ack: 0,
+            start: 0,
+            inputs: vec![PlayerInput::default()],
+            checksums: vec![(0, 1)],
+        }
+        .encode();
+
+        assert_eq!(Packet::decode(&bytes[..bytes.len() - 1]), None);
+        assert_eq!(Packet::decode(&[bytes.as_slice(), &[0]].concat()), None);
+        assert_eq!(Packet::decode(&[9]), None);
+    }
+
+    #[test]
+    fn a_wrong_prediction_marks_the_first_tick_to_roll_back_to() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let peer = socket.local_addr().unwrap();
+        let mut session = NetplaySession::new(socket, peer, 0).unwrap();
+        let right = PlayerInput::from_movement(Vec2::X);
+        session.local_inputs = vec![PlayerInput::default(); 4];
+        for frame in 0..4 {
+            session.inputs(frame);
+        }
+
+        session.receive_inputs(0, &[PlayerInput::default()]);
+        assert_eq!(session.first_incorrect, None);
+        // Out of order input is left until the ticks before it arrive.
+        session.receive_inputs(2, &[right]);
+        assert_eq!(session.confirmed_frames(), 1);
+
+        session.receive_inputs(1, &[PlayerInput::default(), right, right]);
+        assert_eq!(session.confirmed_frames(), 4);
+        assert_eq!(session.first_incorrect, Some(2));
+        assert!(session.predicted.is_empty());
+    }
+
+    #[test]
+    fn rolling_back_past_the_kept_snapshots_ends_the_match() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let peer = socket.local_addr().unwrap();
+        let mut world = World::new();
+        world.insert_resource(NetplaySession::new(socket, peer, 0).unwrap());
+        world.init_resource::<NextState<GameState>>();
+
+        roll_back(&mut world, 3);
+
+        assert!(world.resource::<NetplaySession>().ended);
+        assert!(matches!(*world.resource::<NextState<GameState>>(), NextState::Pending(GameState::GameOver)));
+    }
+
+    #[test]
+    fn a_different_game_config_holds_the_handshake() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let peer = socket.local_addr().unwrap();
+        let mut session = NetplaySession::new(socket, peer, 0).unwrap();
+        let peer_hello = Hello {
+            seed: 1,
+            difficulty: Difficulty::Normal,
+            config: 2,
+            player: 1,
+            heard: true,
+        };
+        session.peer_hello = Some(peer_hello);
+
+        assert_eq!(session.handshake(Some(1), Difficulty::Normal, 3), Handshake::Waiting);
+        assert!(!session.is_running());
+        assert!(matches!(session.handshake(Some(1), Difficulty::Normal, 2), Handshake::Started(_)));
+        assert!(session.is_running());
+    }
+
+    #[test]
+    fn two_instances_cannot_play_the_same_player() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let peer = socket.local_addr().unwrap();
+        let mut session = NetplaySession::new(socket, peer, 1).unwrap();
+        session.peer_hello = Some(Hello {
+            seed: 1,
+            difficulty: Difficulty::Normal,
+            config: 2,
+            player: 1,
+            heard: true,
+        });
+
+        assert_eq!(session.handshake(None, Difficulty::Normal, 2), Handshake::SameSeat);
+        assert!(!session.is_running());
+    }
+
+    #[test]
+    fn bad_netplay_arguments_are_errors() {
+        let off = ReplayMode::Off;
+        let error = |peer, bind, local_player, replay_mode| {
+            NetplaySession::from_arg(Some(peer), bind, local_player, replay_mode).err().map(|error| error.name)
+        };
+
+        assert!(NetplaySession::from_arg(None, None, Some("7"), &off).unwrap().is_none());
+        assert_eq!(error("127.0.0.1:7001", Some("127.0.0.1:0"), Some("2"), &off), Some("--local-player"));
+        assert_eq!(error("nowhere", Some("127.0.0.1:0"), None, &off), Some("--peer"));
+        assert_eq!(error("127.0.0.1:7001", Some("nowhere"), None, &off), Some("--bind"));
+        let recording = ReplayMode::from_arg(None, Some("run.ron")).unwrap();
+        assert_eq!(error("127.0.0.1:7001", Some("127.0.0.1:0"), None, &recording), Some("--peer"));
+        assert_eq!(error("127.0.0.1:7001", Some("127.0.0.1:0"), None, &off), None);
+    }
+}
@@ -0,0 +1,500 @@
+//! Lightweight CPU particles for wall hits, pickups and the player's death.
+//!
+//! Particles are plain values in a `Vec`, not entities, so updating thousands of them is a tight
+//! loop. They are drawn by a pool of sprites that grows to the largest number alive at once. What
+//! every effect looks like is defined in `assets/particles/effects.particles.ron`, which is
+//! reloaded while the game is running.
+//!
+//! Particles are only decoration, so they use the thread's random numbers and never `GameRng`.
+
+use std::collections::HashMap;
+use std::f32::consts::TAU;
+use std::fmt;
+
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, LoadContext};
+use bevy::prelude::*;
+use rand::prelude::*;
+use serde::Deserialize;
+
+use crate::enemy::{EnemyWallCollisionEvent, WallSide};
+use crate::GameState;
+
+pub const PARTICLE_PRESETS_PATH: &str = "particles/effects.particles.ron";
+/// New particles are dropped while this many are alive.
+pub const MAX_PARTICLES: usize = 8192;
+/// Particles are drawn above everything else in the arena.
+pub const PARTICLE_Z: f32 = 2.0;
+
+/// Simulates and draws particles, emitted with `EmitParticles` or by enemies hitting the walls.
+pub struct ParticlePlugin;
+
+impl Plugin for ParticlePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ParticlePresets>()
+            .init_resource::<Particles>()
+            .init_asset::<ParticlePresets>()
+            .init_asset_loader::<ParticlePresetsLoader>()
+            .add_event::<EmitParticles>()
+            .add_systems(Startup, load_particle_presets)
+            .add_systems(
+                Update,
+                (
+                    apply_particle_presets,
+                    emit_wall_hit_particles,
+                    emit_particles,
+                    update_particles.run_if(not(in_state(GameState::Paused))),
+                    draw_particles,
+                )
+                    .chain(),
+            );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+pub enum ParticleEffect {
+    WallHit,
+    StarPickup,
+    PowerUpPickup,
+    PlayerDeath,
+}
+
+/// How one burst of an effect looks.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct EmitterPreset {
+    /// How many particles a burst emits.
+    pub count: usize,
+    /// The shortest and longest a particle lives, in seconds.
+    pub lifetime: (f32, f32),
+    /// The slowest and fastest a particle starts out.
+    pub speed: (f32, f32),
+    /// How far from the direction of the burst a particle may fly, in degrees. A burst without a
+    /// direction flies all around.
+    pub spread: f32,
+    /// How much of its speed a particle keeps after a second.
+    pub drag: f32,
+    /// The colour of a particle over its life, from birth to death, evenly spaced.
+    pub colors: Vec<Color>,
+    /// The size of a particle over its life, like `colors`.
+    pub sizes: Vec<f32>,
+}
+
+impl EmitterPreset {
+    pub fn color(&self, life: f32) -> Color {
+        sample_curve(&self.colors, life, |a, b, t| LinearRgba::from(a).mix(&b.into(), t).into()).unwrap_or(Color::WHITE)
+    }
+
+    pub fn size(&self, life: f32) -> f32 {
+        sample_curve(&self.sizes, life, |a, b, t| a + (b - a) * t).unwrap_or(0.0)
+    }
+}
+
+/// The value of evenly spaced `keys` at `life`, from 0 at the first key to 1 at the last.
+fn sample_curve<T: Copy>(keys: &[T], life: f32, mix: impl Fn(T, T, f32) -> T) -> Option<T> {
+    let last = keys.len().checked_sub(1)?;
+    let position = life.clamp(0.0, 1.0) * last as f32;
+    let index = (position as usize).min(last.saturating_sub(1));
+    let next = (index + 1).min(last);
+    Some(mix(keys[index], keys[next], position - index as f32))
+}
+
+/// Every effect by name, loaded from `assets/particles/effects.particles.ron`. The values in
+/// `Default` are only used until that file has loaded.
+#[derive(Asset, TypePath, Resource, Deserialize, Debug, Clone, PartialEq)]
+pub struct ParticlePresets {
+    pub presets: HashMap<ParticleEffect, EmitterPreset>,
+}
+
+impl ParticlePresets {
+    /// Parses the presets and checks that every lifetime and speed range is in order, as a range
+    /// that ends below its start cannot be sampled.
+    pub fn from_bytes(bytes: &[u8]) -> Result<ParticlePresets, ParticlePresetsLoaderError> {
+        let presets: ParticlePresets = ron::de::from_bytes(bytes)?;
+        for (effect, preset) in &presets.presets {
+            for (name, (min, max)) in [("lifetime", preset.lifetime), ("speed", preset.speed)] {
+                if min.is_nan() || max.is_nan() || min > max {
+                    return Err(ParticlePresetsLoaderError::InvertedRange(*effect, name));
+                }
+            }
+        }
+        Ok(presets)
+    }
+}
+
+impl Default for ParticlePresets {
+    fn default() -> ParticlePresets {
+        ParticlePresets {
+            presets: HashMap::from([
+                (
+                    ParticleEffect::WallHit,
+                    EmitterPreset {
+                        count: 8,
+                        lifetime: (0.2, 0.4),
+                        speed: (80.0, 220.0),
+                        spread: 70.0,
+                        drag: 0.05,
+                        colors: vec![Color::srgba(1.0, 0.9, 0.8, 1.0), Color::srgba(1.0, 0.3, 0.2, 0.0)],
+                        sizes: vec![6.0, 2.0],
+                    },
+                ),
+                (
+                    ParticleEffect::StarPickup,
+                    EmitterPreset {
+                        count: 16,
+                        lifetime: (0.3, 0.6),
+                        speed: (60.0, 180.0),
+                        spread: 180.0,
+                        drag: 0.1,
+                        colors: vec![
+                            Color::srgba(1.0, 1.0, 0.6, 1.0),
+                            Color::srgba(1.0, 0.85, 0.2, 1.0),
+                            Color::srgba(1.0, 0.6, 0.0, 0.0),
+                        ],
+                        sizes: vec![5.0, 7.0, 1.0],
+                    },
+                ),
+                (
+                    ParticleEffect::PowerUpPickup,
+                    EmitterPreset {
+                        count: 24,
+                        lifetime: (0.4, 0.8),
+                        speed: (40.0, 160.0),
+                        spread: 180.0,
+                        drag: 0.2,
+                        colors: vec![Color::srgba(1.0, 1.0, 1.0, 1.0), Color::srgba(0.4, 0.8, 1.0, 0.0)],
+                        sizes: vec![8.0, 3.0],
+                    },
+                ),
+                (
+                    ParticleEffect::PlayerDeath,
+                    EmitterPreset {
+                        count: 120,
+                        lifetime: (0.6, 1.4),
+                        speed: (100.0, 500.0),
+                        spread: 180.0,
+                        drag: 0.02,
+                        colors: vec![
+                            Color::srgba(1.0, 1.0, 1.0, 1.0),
+                            Color::srgba(0.3, 0.6, 1.0, 1.0),
+                            Color::srgba(0.1, 0.1, 0.4, 0.0),
+                        ],
+                        sizes: vec![10.0, 6.0, 2.0],
+                    },
+                ),
+            ]),
+        }
+    }
+}
+
+#[derive(Resource, Deref)]
+pub struct ParticlePresetsHandle(Handle<ParticlePresets>);
+
+#[derive(Default)]
+pub struct ParticlePresetsLoader;
+
+#[derive(Debug)]
+pub enum ParticlePresetsLoaderError {
+    Io(std::io::Error),
+    Format(ron::error::SpannedError),
+    /// The named range of an effect runs from a larger to a smaller value.
+    InvertedRange(ParticleEffect, &'static str),
+}
+
+impl fmt::Display for ParticlePresetsLoaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParticlePresetsLoaderError::Io(error) => write!(f, "could not read the particle presets: {}", error),
+            ParticlePresetsLoaderError::Format(error) => write!(f, "the particle presets are malformed: {}", error),
+            ParticlePresetsLoaderError::InvertedRange(effect, range) => {
+                write!(f, "the {} range of the {:?} particles must not end below its start", range, effect)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParticlePresetsLoaderError {}
+
+impl From<std::io::Error> for ParticlePresetsLoaderError {
+    fn from(error: std::io::Error) -> ParticlePresetsLoaderError {
+        ParticlePresetsLoaderError::Io(error)
+    }
+}
+
+impl From<ron::error::SpannedError> for ParticlePresetsLoaderError {
+    fn from(error: ron::error::SpannedError) -> ParticlePresetsLoaderError {
+        ParticlePresetsLoaderError::Format(error)
+    }
+}
+
+impl AssetLoader for ParticlePresetsLoader {
+    type Asset = ParticlePresets;
+    type Settings = ();
+    type Error = ParticlePresetsLoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<ParticlePresets, ParticlePresetsLoaderError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        ParticlePresets::from_bytes(&bytes)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["particles.ron"]
+    }
+}
+
+/// Asks for a burst of `effect` at `position`, flying toward `direction`, or all around when the
+/// direction is zero.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct EmitParticles {
+    pub effect: ParticleEffect,
+    pub position: Vec2,
+    pub direction: Vec2,
+}
+
+impl EmitParticles {
+    pub fn at(effect: ParticleEffect, position: Vec2) -> EmitParticles {
+        EmitParticles {
+            effect,
+            position,
+            direction: Vec2::ZERO,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Particle {
+    pub effect: ParticleEffect,
+    pub position: Vec2,
+    pub velocity: Vec2,
+    pub age: f32,
+    pub lifetime: f32,
+    pub drag: f32,
+}
+
+/// Every particle alive.
+#[derive(Resource, Default)]
+pub struct Particles {
+    pub particles: Vec<Particle>,
+}
+
+impl Particles {
+    /// Emits one burst of `preset`, as many of its particles as fit under `MAX_PARTICLES`.
+    pub fn emit(
+        &mut self,
+        rng: &mut impl Rng,
+        effect: ParticleEffect,
+        preset: &EmitterPreset,
+        position: Vec2,
+        direction: Vec2,
+    ) {
+        let count = preset.count.min(MAX_PARTICLES - self.particles.len().min(MAX_PARTICLES));
+        let spread = preset.spread.to_radians();
+        let (min_lifetime, max_lifetime) = preset.lifetime;
+        let (min_speed, max_speed) = preset.speed;
+
+        for _ in 0..count {
+            let angle = match direction.try_normalize() {
+                Some(direction) => direction.to_angle() + rng.gen_range(-1.0..=1.0) * spread,
+                None => rng.gen_range(0.0..TAU),
+            };
+            self.particles.push(Particle {
+                effect,
+                position,
+                velocity: Vec2::from_angle(angle) * rng.gen_range(min_speed..=max_speed),
+                age: 0.0,
+                lifetime: rng.gen_range(min_lifetime..=max_lifetime),
+                drag: preset.drag,
+            });
+        }
+    }
+
+    /// Ages and moves every particle by `dt` seconds, removing those that have lived out their
+    /// lifetime.
+    pub fn update(&mut self, dt: f32) {
+        self.particles.retain_mut(|particle| {
+            particle.age += dt;
+            if particle.age >= particle.lifetime {
+                return false;
+            }
+            particle.velocity *= particle.drag.powf(dt);
+            particle.position += particle.velocity * dt;
+            true
+        });
+    }
+}
+
+/// Marks the pooled sprites particles are drawn with.
+#[derive(Component)]
+pub struct ParticleSprite;
+
+pub fn load_particle_presets(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(ParticlePresetsHandle(asset_server.load(PARTICLE_PRESETS_PATH)));
+}
+
+/// Copies the presets asset into the `ParticlePresets` resource every time it is loaded or edited.
+pub fn apply_particle_presets(
+    mut asset_events: EventReader<AssetEvent<ParticlePresets>>,
+    particle_presets_assets: Res<Assets<ParticlePresets>>,
+    handle: Option<Res<ParticlePresetsHandle>>,
+    mut particle_presets: ResMut<ParticlePresets>,
+) {
+    let Some(handle) = handle else {
+        return;
+    };
+
+    for event in asset_events.read() {
+        let (AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id }) = event else {
+            continue;
+        };
+        if *id != handle.id() {
+            continue;
+        }
+        if let Some(presets) = particle_presets_assets.get(*id) {
+            *particle_presets = presets.clone();
+        }
+    }
+}
+
+/// Sprays particles back into the arena from where an enemy hit a wall.
+pub fn emit_wall_hit_particles(
+    mut collision_events: EventReader<EnemyWallCollisionEvent>,
+    mut emit_events: EventWriter<EmitParticles>,
+) {
+    for event in collision_events.read() {
+        let direction = match event.side {
+            WallSide::Left => Vec2::X,
+            WallSide::Right => Vec2::NEG_X,
+            WallSide::Bottom => Vec2::Y,
+            WallSide::Top => Vec2::NEG_Y,
+        };
+        emit_events.send(EmitParticles {
+            effect: ParticleEffect::WallHit,
+            position: event.contact,
+            direction,
+        });
+    }
+}
+
+pub fn emit_particles(
+    mut emit_events: EventReader<EmitParticles>,
+    mut particles: ResMut<Particles>,
+    particle_presets: Res<ParticlePresets>,
+) {
+    let mut rng = thread_rng();
+    for event in emit_events.read() {
+        if let Some(preset) = particle_presets.presets.get(&event.effect) {
+            particles.emit(&mut rng, event.effect, preset, event.position, event.direction);
+        }
+    }
+}
+
+pub fn update_particles(mut particles: ResMut<Particles>, time: Res<Time>) {
+    particles.update(time.delta_secs());
+}
+
+/// Moves one pooled sprite onto every particle and hides the rest, spawning more sprites when
+/// there are more particles than ever before.
+pub fn draw_particles(
+    mut commands: Commands,
+    particles: Res<Particles>,
+    particle_presets: Res<ParticlePresets>,
+    mut sprite_query: Query<(&mut Transform, &mut Sprite, &mut Visibility), With<ParticleSprite>>,
+) {
+    let mut alive = particles.particles.iter();
+
+    for (mut transform, mut sprite, mut visibility) in sprite_query.iter_mut() {
+        let Some(particle) = alive.next() else {
+            if *visibility != Visibility::Hidden {
+                *visibility = Visibility::Hidden;
+            }
+            continue;
+        };
+        let Some(preset) = particle_presets.presets.get(&particle.effect) else {
+            *visibility = Visibility::Hidden;
+            continue;
+        };
+
+        let life = particle.age / particle.lifetime;
+        transform.translation = particle.position.extend(PARTICLE_Z);
+        sprite.color = preset.color(life);
+        sprite.custom_size = Some(Vec2::splat(preset.size(life)));
+        *visibility = Visibility::Inherited;
+    }
+
+    // The new sprites take over the particles left over from the next frame on.
+    for _ in alive {
+        commands.spawn((
+            Sprite::from_color(Color::NONE, Vec2::ZERO),
+            Transform::from_xyz(0.0, 0.0, PARTICLE_Z),
+            Visibility::Hidden,
+            ParticleSprite,
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shipped_presets_match_the_defaults() {
+        let presets =
+            ParticlePresets::from_bytes(include_bytes!("../assets/particles/effects.particles.ron")).unwrap();
+
+        assert_eq!(presets, ParticlePresets::default());
+    }
+
+    #[test]
+    fn presets_with_an_inverted_range_are_rejected() {
+        let presets = b"(presets: { WallHit: (
+            count: 8, lifetime: (0.2, 0.4), speed: (220.0, 80.0), spread: 70.0, drag: 0.05, colors: [], sizes: [],
+        ) })";
+
+        assert!(matches!(
+            ParticlePresets::from_bytes(presets),
+            Err(ParticlePresetsLoaderError::InvertedRange(ParticleEffect::WallHit, "speed"))
+        ));
+    }
+
+    #[test]
+    fn curves_run_from_the_first_key_to_the_last() {
+        let preset = &ParticlePresets::default().presets[&ParticleEffect::StarPickup];
+
+        assert_eq!(preset.size(0.0), 5.0);
+        assert_eq!(preset.size(0.25), 6.0);
+        assert_eq!(preset.size(0.5), 7.0);
+        assert_eq!(preset.size(1.0), 1.0);
+        assert_eq!(preset.color(1.0).alpha(), 0.0);
+    }
+
+    #[test]
+    fn particles_fly_within_the_spread_and_die_of_old_age() {
+        let preset = ParticlePresets::default().presets[&ParticleEffect::WallHit].clone();
+        let mut particles = Particles::default();
+        particles.emit(&mut thread_rng(), ParticleEffect::WallHit, &preset, Vec2::ZERO, Vec2::X);
+
+        assert_eq!(particles.particles.len(), preset.count);
+        for particle in &particles.particles {
+            assert!(particle.velocity.to_angle().abs() <= preset.spread.to_radians() + 1e-5);
+        }
+
+        particles.update(preset.lifetime.1);
+        assert!(particles.particles.is_empty());
+    }
+
+    #[test]
+    fn emitting_stops_at_the_particle_cap() {
+        let preset = ParticlePresets::default().presets[&ParticleEffect::PlayerDeath].clone();
+        let mut particles = Particles::default();
+        for _ in 0..MAX_PARTICLES / preset.count + 2 {
+            particles.emit(&mut thread_rng(), ParticleEffect::PlayerDeath, &preset, Vec2::ZERO, Vec2::ZERO);
+        }
+
+        assert_eq!(particles.particles.len(), MAX_PARTICLES);
+    }
+}
@@ -0,0 +1,339 @@
+//! The players: their input for every fixed tick, their movement inside the arena, and the lives
+//! they lose to enemies. In co-op two players share the arena and the run lasts until both are out.
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::arena::ArenaBounds;
+use crate::audio::{PlaySfx, Sfx};
+use crate::config::GameConfig;
+use crate::input::ActionState;
+use crate::particles::{EmitParticles, ParticleEffect};
+use crate::powerup::{ActivePowerUps, PowerUpKind};
+use crate::projectile::Gun;
+use crate::{arg_value, ArgError, Enemy, GameSet, GameState};
+
+/// How much of the knockback speed is left after a second.
+pub const KNOCKBACK_DECAY: f32 = 0.002;
+/// How many times a second an invulnerable player blinks.
+pub const BLINK_RATE: f32 = 8.0;
+/// The most players that can play at once.
+pub const MAX_PLAYERS: usize = 2;
+/// How far apart co-op players start.
+pub const PLAYER_SPACING: f32 = 200.0;
+
+/// Moves the players, takes a life when an enemy touches one and ends the run once every player is
+/// out.
+pub struct PlayerPlugin;
+
+impl Plugin for PlayerPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PlayerCount>()
+            .add_systems(
+                FixedUpdate,
+                (
+                    (player_movement, confine_player_movement).chain().in_set(GameSet::Movement),
+                    (tick_invulnerability, enemy_hit_player).chain().in_set(GameSet::Collision),
+                ),
+            )
+            .add_systems(Update, blink_invulnerable_player);
+    }
+}
+
+#[derive(Component, Clone, Copy)]
+pub struct Player {
+    /// Which player this is, from 0. It picks the input seat, the score and the tint.
+    pub index: usize,
+}
+
+impl Player {
+    pub fn tint(&self) -> Color {
+        match self.index {
+            0 => Color::WHITE,
+            _ => Color::srgb(0.4, 1.0, 0.6),
+        }
+    }
+
+    pub fn label(&self) -> String {
+        format!("P{}", self.index + 1)
+    }
+}
+
+/// How many players play, one or two for co-op, from `--players`.
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlayerCount(pub usize);
+
+impl Default for PlayerCount {
+    fn default() -> PlayerCount {
+        PlayerCount(1)
+    }
+}
+
+impl PlayerCount {
+    pub fn from_args() -> Result<PlayerCount, ArgError> {
+        PlayerCount::from_arg(arg_value("--players").as_deref())
+    }
+
+    pub fn from_arg(value: Option<&str>) -> Result<PlayerCount, ArgError> {
+        let Some(value) = value else {
+            return Ok(PlayerCount::default());
+        };
+        match value.parse::<usize>() {
+            Ok(players @ 1..=MAX_PLAYERS) => Ok(PlayerCount(players)),
+            _ => Err(ArgError::new("--players", value, format!("a number from 1 to {}", MAX_PLAYERS))),
+        }
+    }
+
+    pub fn is_coop(&self) -> bool {
+        self.0 > 1
+    }
+}
+
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lives {
+    pub remaining: u32,
+}
+
+/// The player was hit recently and cannot be hit again until the timer finishes.
+#[derive(Component)]
+pub struct Invulnerable {
+    pub timer: Timer,
+}
+
+/// The push away from the last enemy that hit the player, slowing down over time.
+#[derive(Component)]
+pub struct Knockback {
+    pub velocity: Vec2,
+}
+
+/// The movement a player asked for on the current fixed tick, and the direction it fired in, if
+/// any, either read from the keyboard, mouse and gamepads or played back from a replay.
+///
+/// It is kept at the precision replays store, so a recording moves the player exactly as the live
+/// input did.
+#[derive(Component, Default, Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PlayerInput {
+    pub x: i8,
+    pub y: i8,
+    /// The direction to fire in, zero when not firing.
+    pub aim_x: i8,
+    pub aim_y: i8,
+}
+
+impl PlayerInput {
+    const SCALE: f32 = i8::MAX as f32;
+
+    /// `movement` is clamped to a length of 1, full speed.
+    pub fn from_movement(movement: Vec2) -> PlayerInput {
+        let movement = movement.clamp_length_max(1.0) * PlayerInput::SCALE;
+        PlayerInput {
+            x: movement.x.round() as i8,
+            y: movement.y.round() as i8,
+            ..default()
+        }
+    }
+
+    /// Fires in `direction` as well, which is normalized.
+    pub fn with_fire(self, direction: Vec2) -> PlayerInput {
+        let aim = direction.normalize_or_zero() * PlayerInput::SCALE;
+        PlayerInput {
+            aim_x: aim.x.round() as i8,
+            aim_y: aim.y.round() as i8,
+            ..self
+        }
+    }
+
+    pub fn movement(&self) -> Vec2 {
+        (Vec2::new(self.x as f32, self.y as f32) / PlayerInput::SCALE).clamp_length_max(1.0)
+    }
+
+    pub fn fire_direction(&self) -> Option<Vec2> {
+        Vec2::new(self.aim_x as f32, self.aim_y as f32).try_normalize()
+    }
+}
+
+/// Spawns every player side by side in the middle of the arena.
+pub fn spawn_player(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    game_config: Res<GameConfig>,
+    player_count: Res<PlayerCount>,
+) {
+    for index in 0..player_count.0 {
+        let x = (index as f32 - (player_count.0 - 1) as f32 / 2.0) * PLAYER_SPACING;
+        let lives = Lives {
+            remaining: game_config.player_lives,
+        };
+        commands.spawn((
+            player_bundle(&asset_server, &game_config, Player { index }, lives),
+            Transform::from_xyz(x, 0.0, 0.0),
+            ActivePowerUps::default(),
+            Gun::default(),
+        ));
+    }
+}
+
+pub(crate) fn player_bundle(
+    asset_server: &AssetServer,
+    game_config: &GameConfig,
+    player: Player,
+    lives: Lives,
+) -> impl Bundle {
+    (
+        Sprite {
+            image: asset_server.load("sprites/ball_blue_large.png"),
+            custom_size: Some(Vec2::splat(game_config.player_size)),
+            color: player.tint(),
+            ..default()
+        },
+        player,
+        PlayerInput::default(),
+        lives,
+    )
+}
+
+/// Reads every player's input from their seat. Only the first player aims with the mouse.
+pub fn read_player_input(
+    action_state: Res<ActionState>,
+    mut player_query: Query<(&Player, &Transform, &mut PlayerInput)>,
+) {
+    for (player, transform, mut player_input) in player_query.iter_mut() {
+        let seat = &action_state.seats[player.index];
+        let cursor = action_state.cursor.filter(|_| player.index == 0);
+        *player_input = PlayerInput::from_movement(seat.movement());
+        if let Some(direction) = seat.fire_direction(transform.translation.truncate(), cursor) {
+            *player_input = player_input.with_fire(direction);
+        }
+    }
+}
+
+pub fn player_movement(
+    mut commands: Commands,
+    mut player_query: Query<(Entity, &PlayerInput, &mut Transform, Option<&mut Knockback>), With<Player>>,
+    time: Res<Time>,
+    game_config: Res<GameConfig>,
+) {
+    for (entity, player_input, mut transform, knockback) in player_query.iter_mut() {
+        let direction = player_input.movement().extend(0.0);
+
+        transform.translation += direction * game_config.player_speed * time.delta().as_secs_f32();
+
+        if let Some(mut knockback) = knockback {
+            transform.translation += knockback.velocity.extend(0.0) * time.delta_secs();
+            knockback.velocity *= KNOCKBACK_DECAY.powf(time.delta_secs());
+            if knockback.velocity.length() < 1.0 {
+                commands.entity(entity).remove::<Knockback>();
+            }
+        }
+    }
+}
+
+pub fn confine_player_movement(
+    mut player_query: Query<(&mut Transform, Option<&ActivePowerUps>), With<Player>>,
+    arena_bounds: Res<ArenaBounds>,
+    game_config: Res<GameConfig>,
+) {
+    for (mut player_transform, active_power_ups) in player_query.iter_mut() {
+        let player_size = active_power_ups
+            .map_or(game_config.player_size, |active_power_ups| active_power_ups.player_size(&game_config));
+        let position = arena_bounds.clamp(player_transform.translation.truncate(), player_size);
+        player_transform.translation = position.extend(player_transform.translation.z);
+    }
+}
+
+/// Ends the invulnerability of a player whose timer has run out.
+pub fn tick_invulnerability(
+    mut commands: Commands,
+    mut player_query: Query<(Entity, &mut Invulnerable, &mut Sprite), With<Player>>,
+    time: Res<Time>,
+) {
+    for (entity, mut invulnerable, mut sprite) in player_query.iter_mut() {
+        if invulnerable.timer.tick(time.delta()).finished() {
+            sprite.color.set_alpha(1.0);
+            commands.entity(entity).remove::<Invulnerable>();
+        }
+    }
+}
+
+type VulnerablePlayerFilter = (With<Player>, Without<Invulnerable>);
+
+/// Takes a life when an enemy touches a player, pushing the player away and making it invulnerable
+/// for a while. A player is out with its last life, and the run ends when every player is out. A
+/// shield takes the hit instead of a life.
+#[allow(clippy::too_many_arguments)]
+pub fn enemy_hit_player(
+    mut commands: Commands,
+    mut player_query: Query<(Entity, &Transform, &mut Lives, Option<&mut ActivePowerUps>), VulnerablePlayerFilter>,
+    all_players_query: Query<(), With<Player>>,
+    enemy_query: Query<(Entity, &Transform), With<Enemy>>,
+    mut sfx_events: EventWriter<PlaySfx>,
+    mut particle_events: EventWriter<EmitParticles>,
+    mut next_state: ResMut<NextState<GameState>>,
+    game_config: Res<GameConfig>,
+) {
+    let mut players_left = all_players_query.iter().count();
+    let mut shielded_enemies = Vec::new();
+    for (player_entity, player_transform, mut lives, mut active_power_ups) in player_query.iter_mut() {
+        let player_size = active_power_ups
+            .as_ref()
+            .map_or(game_config.player_size, |active_power_ups| active_power_ups.player_size(&game_config));
+        for (enemy_entity, enemy_transform) in enemy_query.iter() {
+            if shielded_enemies.contains(&enemy_entity) {
+                continue;
+            }
+            let distance = player_transform
+                .translation
+                .distance(enemy_transform.translation);
+            let player_radius = player_size / 2.0;
+            let enemy_radius = game_config.enemy_size * enemy_transform.scale.x / 2.0;
+            if distance < player_radius + enemy_radius {
+                let shielded = active_power_ups
+                    .as_mut()
+                    .is_some_and(|active_power_ups| active_power_ups.consume(PowerUpKind::Shield));
+                if shielded {
+                    // The shield breaks the enemy instead, so it cannot hit again on the next tick.
+                    shielded_enemies.push(enemy_entity);
+                    commands.entity(enemy_entity).despawn();
+                    sfx_events.send(PlaySfx::at(Sfx::EnemyDestroyed, enemy_transform.translation.truncate()));
+                    break;
+                }
+                lives.remaining = lives.remaining.saturating_sub(1);
+                if lives.remaining == 0 {
+                    players_left -= 1;
+                    let position = player_transform.translation.truncate();
+                    if players_left == 0 {
+                        next_state.set(GameState::GameOver);
+                        sfx_events.send(PlaySfx::centered(Sfx::Explosion));
+                    } else {
+                        sfx_events.send(PlaySfx::at(Sfx::Explosion, position));
+                    }
+                    particle_events.send(EmitParticles::at(ParticleEffect::PlayerDeath, position));
+                    commands.entity(player_entity).despawn();
+                } else {
+                    let away = (player_transform.translation - enemy_transform.translation)
+                        .truncate()
+                        .normalize_or(Vec2::Y);
+                    sfx_events.send(PlaySfx::at(Sfx::PlayerHit, player_transform.translation.truncate()));
+                    commands.entity(player_entity).insert((
+                        Invulnerable {
+                            timer: Timer::from_seconds(game_config.invulnerability_time, TimerMode::Once),
+                        },
+                        Knockback {
+                            velocity: away * game_config.knockback_speed,
+                        },
+                    ));
+                }
+                // Touching several enemies at once still only costs one life.
+                break;
+            }
+        }
+    }
+}
+
+/// Blinks an invulnerable player by fading its sprite in and out.
+pub fn blink_invulnerable_player(mut player_query: Query<(&Invulnerable, &mut Sprite), With<Player>>) {
+    for (invulnerable, mut sprite) in player_query.iter_mut() {
+        let visible = ((invulnerable.timer.elapsed_secs() * BLINK_RATE) as u32).is_multiple_of(2);
+        sprite.color.set_alpha(if visible { 1.0 } else { 0.25 });
+    }
+}
@@ -0,0 +1,357 @@
+//! Power-ups: pickups that spawn on a timer and give the player a timed effect when touched.
+//!
+//! Picking up a kind that is already active refreshes it to its full duration, it does not add up.
+//! Different kinds stack and run out independently.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use rand::prelude::*;
+
+use crate::arena::ArenaBounds;
+use crate::audio::{PlaySfx, Sfx};
+use crate::config::GameConfig;
+use crate::enemy::{self, EnemyClock};
+use crate::particles::{EmitParticles, ParticleEffect};
+use crate::player::PlayerCount;
+use crate::{GameRng, GameSet, Player};
+
+pub const POWER_UP_SIZE: f32 = 32.0;
+/// How often a new power-up appears.
+pub const POWER_UP_SPAWN_TIME: f32 = 10.0;
+/// How long a power-up stays in the arena before it disappears untouched.
+pub const POWER_UP_LIFETIME: f32 = 8.0;
+pub const MAX_POWER_UPS: usize = 2;
+/// How fast enemies move during slow motion.
+pub const SLOW_MOTION_SPEED: f32 = 0.4;
+/// How big the player is while shrunk, for both its sprite and its collisions.
+pub const SHRINK_SCALE: f32 = 0.5;
+
+/// Spawns power-ups, applies their effects and ends them when they run out.
+pub struct PowerUpPlugin;
+
+impl Plugin for PowerUpPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PowerUpSpawnTimer>()
+            .add_systems(
+                FixedUpdate,
+                (
+                    tick_power_ups.in_set(GameSet::Movement).before(enemy::advance_enemy_clock),
+                    player_hit_power_up.in_set(GameSet::Collision).after(crate::player::enemy_hit_player),
+                    (tick_power_up_spawn_timer, expire_power_up_pickups, spawn_power_ups_over_time)
+                        .chain()
+                        .in_set(GameSet::Spawn)
+                        .after(crate::spawn_stars_over_time)
+                        .before(enemy::tick_enemy_spawner),
+                ),
+            )
+            .add_systems(Update, update_power_up_text);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PowerUpKind {
+    /// Ignores the next enemy hit.
+    Shield,
+    /// Slows the enemies down.
+    SlowMotion,
+    /// Makes the player smaller and so harder to hit.
+    Shrink,
+}
+
+impl PowerUpKind {
+    pub const ALL: [PowerUpKind; 3] = [PowerUpKind::Shield, PowerUpKind::SlowMotion, PowerUpKind::Shrink];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PowerUpKind::Shield => "Shield",
+            PowerUpKind::SlowMotion => "Slow",
+            PowerUpKind::Shrink => "Shrink",
+        }
+    }
+
+    pub fn color(self) -> Color {
+        match self {
+            PowerUpKind::Shield => Color::srgb(0.3, 0.8, 1.0),
+            PowerUpKind::SlowMotion => Color::srgb(0.7, 0.4, 1.0),
+            PowerUpKind::Shrink => Color::srgb(0.4, 1.0, 0.4),
+        }
+    }
+
+    /// How long the effect lasts once picked up.
+    pub fn duration(self) -> f32 {
+        match self {
+            PowerUpKind::Shield => 8.0,
+            PowerUpKind::SlowMotion => 5.0,
+            PowerUpKind::Shrink => 6.0,
+        }
+    }
+}
+
+/// A power-up waiting in the arena to be picked up.
+#[derive(Component, Clone)]
+pub struct PowerUp {
+    pub kind: PowerUpKind,
+    pub lifetime: Timer,
+}
+
+/// The effects the player has picked up and the time each has left.
+#[derive(Component, Default, Debug, Clone)]
+pub struct ActivePowerUps {
+    // Ordered, so effects always run out and show in the same order.
+    timers: BTreeMap<PowerUpKind, Timer>,
+}
+
+impl ActivePowerUps {
+    /// Starts `kind`, or restarts it with its full duration if it is already running.
+    pub fn activate(&mut self, kind: PowerUpKind) {
+        self.timers
+            .insert(kind, Timer::from_seconds(kind.duration(), TimerMode::Once));
+    }
+
+    pub fn is_active(&self, kind: PowerUpKind) -> bool {
+        self.timers.contains_key(&kind)
+    }
+
+    /// Ends `kind` early, returning whether it was active.
+    pub fn consume(&mut self, kind: PowerUpKind) -> bool {
+        self.timers.remove(&kind).is_some()
+    }
+
+    pub fn remaining(&self, kind: PowerUpKind) -> Option<Duration> {
+        self.timers.get(&kind).map(Timer::remaining)
+    }
+
+    /// Advances every effect by `delta` and removes those that ran out, returning their kinds.
+    pub fn tick(&mut self, delta: Duration) -> Vec<PowerUpKind> {
+        let mut expired = Vec::new();
+        self.timers.retain(|&kind, timer| {
+            let finished = timer.tick(delta).finished();
+            if finished {
+                expired.push(kind);
+            }
+            !finished
+        });
+        expired
+    }
+
+    /// The countdown of every active effect, such as `Shield 4s`.
+    pub fn summary(&self) -> String {
+        self.timers
+            .iter()
+            .map(|(kind, timer)| format!("{} {}s", kind.name(), timer.remaining_secs().ceil()))
+            .collect::<Vec<_>>()
+            .join("  ")
+    }
+
+    /// The size the player collides with, smaller while shrunk.
+    pub fn player_size(&self, game_config: &GameConfig) -> f32 {
+        if self.is_active(PowerUpKind::Shrink) {
+            game_config.player_size * SHRINK_SCALE
+        } else {
+            game_config.player_size
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct PowerUpSpawnTimer {
+    pub timer: Timer,
+}
+
+impl Default for PowerUpSpawnTimer {
+    fn default() -> PowerUpSpawnTimer {
+        PowerUpSpawnTimer {
+            timer: Timer::from_seconds(POWER_UP_SPAWN_TIME, TimerMode::Repeating),
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct PowerUpText;
+
+pub fn reset_power_up_spawner(mut power_up_spawn_timer: ResMut<PowerUpSpawnTimer>) {
+    power_up_spawn_timer.timer.reset();
+}
+
+pub fn spawn_power_up(commands: &mut Commands, rng: &mut impl Rng, arena_bounds: &ArenaBounds) {
+    let kind = *PowerUpKind::ALL.choose(rng).unwrap();
+    let power_up = PowerUp {
+        kind,
+        lifetime: Timer::from_seconds(POWER_UP_LIFETIME, TimerMode::Once),
+    };
+    commands.spawn(power_up_bundle(power_up, arena_bounds.random_position(rng, POWER_UP_SIZE)));
+}
+
+pub(crate) fn power_up_bundle(power_up: PowerUp, position: Vec3) -> impl Bundle {
+    (
+        Sprite::from_color(power_up.kind.color(), Vec2::splat(POWER_UP_SIZE)),
+        Transform::from_translation(position),
+        power_up,
+    )
+}
+
+fn tick_power_up_spawn_timer(mut power_up_spawn_timer: ResMut<PowerUpSpawnTimer>, time: Res<Time>) {
+    power_up_spawn_timer.timer.tick(time.delta());
+}
+
+/// Removes the power-ups that were left lying around for too long.
+fn expire_power_up_pickups(
+    mut commands: Commands,
+    mut power_up_query: Query<(Entity, &mut PowerUp)>,
+    time: Res<Time>,
+) {
+    for (entity, mut power_up) in power_up_query.iter_mut() {
+        if power_up.lifetime.tick(time.delta()).finished() {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+fn spawn_power_ups_over_time(
+    mut commands: Commands,
+    arena_bounds: Res<ArenaBounds>,
+    power_up_spawn_timer: Res<PowerUpSpawnTimer>,
+    power_up_query: Query<(), With<PowerUp>>,
+    mut rng: ResMut<GameRng>,
+) {
+    if power_up_spawn_timer.timer.just_finished() && power_up_query.iter().count() < MAX_POWER_UPS {
+        spawn_power_up(&mut commands, &mut **rng, &arena_bounds);
+    }
+}
+
+/// Gives a power-up to the player touching it. When both players touch it, the first player gets it.
+fn player_hit_power_up(
+    mut commands: Commands,
+    mut player_query: Query<(&Player, &Transform, &mut ActivePowerUps)>,
+    power_up_query: Query<(Entity, &Transform, &PowerUp)>,
+    mut sfx_events: EventWriter<PlaySfx>,
+    mut particle_events: EventWriter<EmitParticles>,
+    game_config: Res<GameConfig>,
+) {
+    let mut players: Vec<_> = player_query.iter_mut().collect();
+    players.sort_by_key(|(player, _, _)| player.index);
+    let mut taken = Vec::new();
+    for (_, player_transform, active_power_ups) in players.iter_mut() {
+        let player_radius = active_power_ups.player_size(&game_config) / 2.0;
+        for (power_up_entity, power_up_transform, power_up) in power_up_query.iter() {
+            if taken.contains(&power_up_entity) {
+                continue;
+            }
+            let distance = player_transform
+                .translation
+                .distance(power_up_transform.translation);
+            if distance < player_radius + POWER_UP_SIZE / 2.0 {
+                active_power_ups.activate(power_up.kind);
+                sfx_events.send(PlaySfx::centered(Sfx::StarPickup));
+                let position = power_up_transform.translation.truncate();
+                particle_events.send(EmitParticles::at(ParticleEffect::PowerUpPickup, position));
+                commands.entity(power_up_entity).despawn();
+                taken.push(power_up_entity);
+            }
+        }
+    }
+}
+
+/// Counts down the players' effects and applies the ones that change the world: slow motion sets
+/// the speed of the enemy clock for everyone, shrink the size of the player and a shield tints it.
+pub fn tick_power_ups(
+    mut player_query: Query<(&Player, &mut ActivePowerUps, &mut Transform, &mut Sprite)>,
+    mut enemy_time: ResMut<Time<EnemyClock>>,
+    time: Res<Time>,
+) {
+    let mut relative_speed = 1.0;
+
+    for (player, mut active_power_ups, mut transform, mut sprite) in player_query.iter_mut() {
+        active_power_ups.tick(time.delta());
+
+        if active_power_ups.is_active(PowerUpKind::SlowMotion) {
+            relative_speed = SLOW_MOTION_SPEED;
+        }
+
+        let scale = if active_power_ups.is_active(PowerUpKind::Shrink) { SHRINK_SCALE } else { 1.0 };
+        transform.scale = Vec3::new(scale, scale, 1.0);
+
+        // Keep the alpha, it is blinking while the player is invulnerable.
+        let tint = if active_power_ups.is_active(PowerUpKind::Shield) {
+            PowerUpKind::Shield.color()
+        } else {
+            player.tint()
+        };
+        sprite.color = tint.with_alpha(sprite.color.alpha());
+    }
+
+    enemy_time.context_mut().relative_speed = relative_speed;
+}
+
+fn update_power_up_text(
+    player_query: Query<(&Player, &ActivePowerUps)>,
+    player_count: Res<PlayerCount>,
+    mut text_query: Query<&mut Text, With<PowerUpText>>,
+) {
+    let mut players: Vec<(&Player, &ActivePowerUps)> = player_query.iter().collect();
+    players.sort_by_key(|(player, _)| player.index);
+    let summary = players
+        .iter()
+        .map(|(player, active_power_ups)| (player, active_power_ups.summary()))
+        .filter(|(_, summary)| !summary.is_empty())
+        .map(|(player, summary)| {
+            if player_count.is_coop() {
+                format!("{} {}", player.label(), summary)
+            } else {
+                summary
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    for mut text in text_query.iter_mut() {
+        // Only touch the text when the countdown changes, so the HUD is not laid out every frame.
+        if text.0 != summary {
+            text.0 = summary.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picking_up_an_active_kind_refreshes_it() {
+        let mut active_power_ups = ActivePowerUps::default();
+        active_power_ups.activate(PowerUpKind::Shield);
+        active_power_ups.tick(Duration::from_secs(5));
+        active_power_ups.activate(PowerUpKind::Shield);
+
+        let full = Duration::from_secs_f32(PowerUpKind::Shield.duration());
+        assert_eq!(active_power_ups.remaining(PowerUpKind::Shield), Some(full));
+    }
+
+    #[test]
+    fn different_kinds_stack_and_expire_independently() {
+        let mut active_power_ups = ActivePowerUps::default();
+        active_power_ups.activate(PowerUpKind::SlowMotion);
+        active_power_ups.activate(PowerUpKind::Shrink);
+        assert_eq!(active_power_ups.summary(), "Slow 5s  Shrink 6s");
+
+        let expired = active_power_ups.tick(Duration::from_secs_f32(PowerUpKind::SlowMotion.duration()));
+        assert_eq!(expired, vec![PowerUpKind::SlowMotion]);
+        assert!(!active_power_ups.is_active(PowerUpKind::SlowMotion));
+        assert!(active_power_ups.is_active(PowerUpKind::Shrink));
+
+        let expired = active_power_ups.tick(Duration::from_secs(1));
+        assert_eq!(expired, vec![PowerUpKind::Shrink]);
+        assert_eq!(active_power_ups.summary(), "");
+    }
+
+    #[test]
+    fn shrink_reduces_the_collision_size() {
+        let game_config = GameConfig::default();
+        let mut active_power_ups = ActivePowerUps::default();
+        assert_eq!(active_power_ups.player_size(&game_config), game_config.player_size);
+
+        active_power_ups.activate(PowerUpKind::Shrink);
+        assert!(active_power_ups.player_size(&game_config) < game_config.player_size);
+    }
+}
@@ -0,0 +1,246 @@
+//! Projectiles the players fire at enemies, toward the mouse cursor or where the right stick
+//! points.
+//!
+//! Projectiles are pooled: they are spawned once, hidden while unused and reused for every shot
+//! instead of being spawned and despawned many times a second.
+
+use std::cmp::Ordering;
+use std::time::Duration;
+
+use bevy::prelude::*;
+
+use crate::arena::ArenaBounds;
+use crate::audio::{PlaySfx, Sfx};
+use crate::config::GameConfig;
+use crate::{player, Enemy, GameSet, Player, PlayerInput, Score};
+
+pub const PROJECTILE_SIZE: f32 = 16.0;
+pub const PROJECTILE_SPEED: f32 = 900.0;
+pub const PROJECTILE_COLOR: Color = Color::srgb(1.0, 0.9, 0.3);
+/// The shortest time between two shots.
+pub const FIRE_COOLDOWN: f32 = 0.25;
+/// How many projectiles are spawned up front. The pool grows if more are ever in flight at once.
+pub const PROJECTILE_POOL_SIZE: usize = 16;
+/// The score for shooting down an enemy.
+pub const ENEMY_POINTS: u32 = 3;
+
+/// Fires the players' projectiles, moves them and destroys the enemies they hit.
+pub struct ProjectilePlugin;
+
+impl Plugin for ProjectilePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ProjectilePool>()
+            .add_systems(Startup, fill_projectile_pool)
+            .add_systems(
+                FixedUpdate,
+                (
+                    (fire_projectiles, move_projectiles)
+                        .chain()
+                        .in_set(GameSet::Movement)
+                        .after(player::confine_player_movement),
+                    projectile_hit_enemy
+                        .in_set(GameSet::Collision)
+                        .before(player::tick_invulnerability),
+                ),
+            );
+    }
+}
+
+/// A pooled projectile. Only the ones `in_flight` are visible and move.
+#[derive(Component, Clone)]
+pub struct Projectile {
+    pub velocity: Vec2,
+    pub in_flight: bool,
+    /// The index of the player who fired it, who scores its hits.
+    pub owner: usize,
+}
+
+/// The cooldown between the shots of a player.
+#[derive(Component, Clone)]
+pub struct Gun {
+    pub cooldown: Timer,
+}
+
+impl Default for Gun {
+    fn default() -> Gun {
+        let mut cooldown = Timer::from_seconds(FIRE_COOLDOWN, TimerMode::Once);
+        // Ready to fire straight away.
+        cooldown.tick(Duration::from_secs_f32(FIRE_COOLDOWN));
+        Gun { cooldown }
+    }
+}
+
+/// The projectiles that are not in flight, ready to be fired.
+#[derive(Resource, Default)]
+pub struct ProjectilePool {
+    pub free: Vec<Entity>,
+}
+
+impl ProjectilePool {
+    /// Hides a projectile and puts it back in the pool.
+    pub fn release(&mut self, entity: Entity, projectile: &mut Projectile, visibility: &mut Visibility) {
+        if projectile.in_flight {
+            projectile.in_flight = false;
+            *visibility = Visibility::Hidden;
+            self.free.push(entity);
+        }
+    }
+}
+
+pub(crate) fn projectile_bundle(asset_server: &AssetServer, projectile: Projectile, position: Vec2) -> impl Bundle {
+    let visibility = if projectile.in_flight {
+        Visibility::Inherited
+    } else {
+        Visibility::Hidden
+    };
+    (
+        Sprite {
+            image: asset_server.load("sprites/ball_blue_large.png"),
+            custom_size: Some(Vec2::splat(PROJECTILE_SIZE)),
+            color: PROJECTILE_COLOR,
+            ..default()
+        },
+        Transform::from_translation(position.extend(0.5)),
+        visibility,
+        projectile,
+    )
+}
+
+fn fill_projectile_pool(mut commands: Commands, mut pool: ResMut<ProjectilePool>, asset_server: Res<AssetServer>) {
+    for _ in 0..PROJECTILE_POOL_SIZE {
+        let projectile = Projectile {
+            velocity: Vec2::ZERO,
+            in_flight: false,
+            owner: 0,
+        };
+        pool.free.push(commands.spawn(projectile_bundle(&asset_server, projectile, Vec2::ZERO)).id());
+    }
+}
+
+/// Puts every projectile back in the pool, at the start of a run and on the way to the menu.
+pub fn recall_projectiles(
+    mut pool: ResMut<ProjectilePool>,
+    mut projectile_query: Query<(Entity, &mut Projectile, &mut Visibility)>,
+) {
+    for (entity, mut projectile, mut visibility) in projectile_query.iter_mut() {
+        pool.release(entity, &mut projectile, &mut visibility);
+    }
+}
+
+/// Fires a projectile from every player in the direction of their input this tick, once their gun
+/// has cooled down.
+pub fn fire_projectiles(
+    mut commands: Commands,
+    mut player_query: Query<(&Player, &PlayerInput, &Transform, &mut Gun)>,
+    mut projectile_query: Query<(&mut Projectile, &mut Transform, &mut Visibility), Without<Player>>,
+    mut pool: ResMut<ProjectilePool>,
+    mut sfx_events: EventWriter<PlaySfx>,
+    asset_server: Res<AssetServer>,
+    time: Res<Time>,
+) {
+    let mut players: Vec<_> = player_query.iter_mut().collect();
+    // Shots take projectiles from the pool in player order.
+    players.sort_by_key(|(player, ..)| player.index);
+    for (player, player_input, player_transform, gun) in players.iter_mut() {
+        gun.cooldown.tick(time.delta());
+
+        let Some(direction) = player_input.fire_direction() else {
+            continue;
+        };
+        if !gun.cooldown.finished() {
+            continue;
+        }
+        gun.cooldown.reset();
+
+        let position = player_transform.translation.truncate();
+        let fired = Projectile {
+            velocity: direction * PROJECTILE_SPEED,
+            in_flight: true,
+            owner: player.index,
+        };
+        match pool.free.pop() {
+            Some(entity) => {
+                if let Ok((mut projectile, mut transform, mut visibility)) = projectile_query.get_mut(entity) {
+                    *projectile = fired;
+                    transform.translation = position.extend(transform.translation.z);
+                    *visibility = Visibility::Inherited;
+                }
+            }
+            None => {
+                commands.spawn(projectile_bundle(&asset_server, fired, position));
+            }
+        }
+        sfx_events.send(PlaySfx::at(Sfx::Laser, position));
+    }
+}
+
+/// Moves the projectiles in flight, returning the ones that left the arena to the pool.
+pub fn move_projectiles(
+    mut projectile_query: Query<(Entity, &mut Projectile, &mut Transform, &mut Visibility)>,
+    mut pool: ResMut<ProjectilePool>,
+    arena_bounds: Res<ArenaBounds>,
+    time: Res<Time>,
+) {
+    for (entity, mut projectile, mut transform, mut visibility) in projectile_query.iter_mut() {
+        if !projectile.in_flight {
+            continue;
+        }
+
+        transform.translation += projectile.velocity.extend(0.0) * time.delta_secs();
+        let position = transform.translation.truncate();
+        if position.cmplt(arena_bounds.min).any() || position.cmpgt(arena_bounds.max).any() {
+            pool.release(entity, &mut projectile, &mut visibility);
+        }
+    }
+}
+
+/// Destroys the enemies that projectiles touch, for points. A projectile is used up by the first
+/// enemy it hits.
+pub fn projectile_hit_enemy(
+    mut commands: Commands,
+    mut projectile_query: Query<(Entity, &mut Projectile, &Transform, &mut Visibility)>,
+    enemy_query: Query<(Entity, &Transform), With<Enemy>>,
+    mut pool: ResMut<ProjectilePool>,
+    mut sfx_events: EventWriter<PlaySfx>,
+    mut score: ResMut<Score>,
+    game_config: Res<GameConfig>,
+) {
+    // Hits are checked in an order that does not depend on the entities, which a rollback reshuffles,
+    // so both instances of an online match credit an enemy two players hit at once to the same one.
+    let mut projectiles: Vec<_> = projectile_query
+        .iter_mut()
+        .filter(|(_, projectile, _, _)| projectile.in_flight)
+        .collect();
+    projectiles.sort_by(|(_, a, a_transform, _), (_, b, b_transform, _)| {
+        a.owner.cmp(&b.owner).then_with(|| position_order(a_transform, b_transform))
+    });
+    let mut enemies: Vec<_> = enemy_query.iter().collect();
+    enemies.sort_by(|(_, a), (_, b)| position_order(a, b));
+    let mut destroyed = Vec::new();
+
+    for (entity, mut projectile, projectile_transform, mut visibility) in projectiles {
+        for &(enemy_entity, enemy_transform) in &enemies {
+            if destroyed.contains(&enemy_entity) {
+                continue;
+            }
+            let distance = projectile_transform
+                .translation
+                .truncate()
+                .distance(enemy_transform.translation.truncate());
+            let projectile_radius = PROJECTILE_SIZE / 2.0;
+            let enemy_radius = game_config.enemy_size * enemy_transform.scale.x / 2.0;
+            if distance < projectile_radius + enemy_radius {
+                destroyed.push(enemy_entity);
+                commands.entity(enemy_entity).despawn();
+                score.add(projectile.owner, ENEMY_POINTS);
+                sfx_events.send(PlaySfx::at(Sfx::EnemyDestroyed, enemy_transform.translation.truncate()));
+                pool.release(entity, &mut projectile, &mut visibility);
+                break;
+            }
+        }
+    }
+}
+
+fn position_order(a: &Transform, b: &Transform) -> Ordering {
+    a.translation.x.total_cmp(&b.translation.x).then_with(|| a.translation.y.total_cmp(&b.translation.y))
+}
@@ -0,0 +1,271 @@
+//! Recording and playback of the players' input, one entry per fixed tick.
+//!
+//! Record a run with `--record <path>` and play it back with `--replay <path>`. The replay stores
+//! the seed, difficulty and number of players of the run, so together with the fixed timestep the
+//! whole game plays out the same way. It also keeps a checksum of the game config, since the run
+//! only plays out the same way with the same tunables.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{Difficulty, GameConfig};
+use crate::player::{PlayerCount, MAX_PLAYERS};
+use crate::{arg_value, ArgError, GameRng, Player, PlayerInput};
+
+/// Bumped whenever the layout of `Replay` or the meaning of a tick changes.
+pub const REPLAY_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Replay {
+    pub version: u32,
+    pub seed: u64,
+    pub difficulty: Difficulty,
+    pub players: usize,
+    /// The `GameConfig::checksum` of the config the run was played with.
+    pub config: u64,
+    /// The input of every player on every fixed tick, by player index. Players who are out idle.
+    pub ticks: Vec<Vec<PlayerInput>>,
+}
+
+impl Replay {
+    pub fn new(seed: u64, difficulty: Difficulty, players: usize, config: u64) -> Replay {
+        Replay {
+            version: REPLAY_VERSION,
+            seed,
+            difficulty,
+            players,
+            config,
+            ticks: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Replay, ReplayError> {
+        let contents = fs::read_to_string(path)?;
+        let replay: Replay = ron::from_str(&contents)?;
+        if replay.version != REPLAY_VERSION {
+            return Err(ReplayError::Version(replay.version));
+        }
+        if !(1..=MAX_PLAYERS).contains(&replay.players) {
+            return Err(ReplayError::Players(replay.players));
+        }
+        Ok(replay)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ReplayError> {
+        let contents = ron::to_string(self)?;
+        fs::write(path, contents)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    Format(ron::Error),
+    Version(u32),
+    Players(usize),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Io(error) => write!(f, "could not access the replay file: {}", error),
+            ReplayError::Format(error) => write!(f, "the replay file is malformed: {}", error),
+            ReplayError::Version(version) => write!(
+                f,
+                "the replay file has version {}, but only version {} is supported",
+                version, REPLAY_VERSION
+            ),
+            ReplayError::Players(players) => write!(
+                f,
+                "the replay file has {} players, but only 1 to {} can play",
+                players, MAX_PLAYERS
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<io::Error> for ReplayError {
+    fn from(error: io::Error) -> ReplayError {
+        ReplayError::Io(error)
+    }
+}
+
+impl From<ron::Error> for ReplayError {
+    fn from(error: ron::Error) -> ReplayError {
+        ReplayError::Format(error)
+    }
+}
+
+impl From<ron::error::SpannedError> for ReplayError {
+    fn from(error: ron::error::SpannedError) -> ReplayError {
+        ReplayError::Format(error.code)
+    }
+}
+
+#[derive(Resource, Debug, Default)]
+pub enum ReplayMode {
+    #[default]
+    Off,
+    Record {
+        path: PathBuf,
+        replay: Replay,
+        saved: bool,
+    },
+    Playback {
+        replay: Replay,
+        tick: usize,
+    },
+}
+
+impl ReplayMode {
+    pub fn from_args() -> Result<ReplayMode, ArgError> {
+        ReplayMode::from_arg(arg_value("--replay").as_deref(), arg_value("--record").as_deref())
+    }
+
+    /// Plays back the replay at `replay` if given, or else records to `record`.
+    pub fn from_arg(replay: Option<&str>, record: Option<&str>) -> Result<ReplayMode, ArgError> {
+        if let Some(path) = replay {
+            return match Replay::load(Path::new(path)) {
+                Ok(replay) => Ok(ReplayMode::Playback { replay, tick: 0 }),
+                Err(error) => Err(ArgError::new("--replay", path, format!("a replay the game can play ({})", error))),
+            };
+        }
+        Ok(match record {
+            Some(path) => ReplayMode::Record {
+                path: PathBuf::from(path),
+                replay: Replay::new(0, Difficulty::default(), PlayerCount::default().0, 0),
+                saved: false,
+            },
+            None => ReplayMode::Off,
+        })
+    }
+}
+
+pub fn is_recording(replay_mode: Res<ReplayMode>) -> bool {
+    matches!(*replay_mode, ReplayMode::Record { .. })
+}
+
+pub fn is_playing_back(replay_mode: Res<ReplayMode>) -> bool {
+    matches!(*replay_mode, ReplayMode::Playback { .. })
+}
+
+/// Starts a fresh recording, or rewinds the playback, at the start of every run. Playing back a
+/// replay recorded with another game config warns that it may not play out the same way.
+pub fn start_replay(
+    mut replay_mode: ResMut<ReplayMode>,
+    rng: Res<GameRng>,
+    difficulty: Res<Difficulty>,
+    player_count: Res<PlayerCount>,
+    game_config: Res<GameConfig>,
+) {
+    match &mut *replay_mode {
+        ReplayMode::Off => {}
+        ReplayMode::Record { replay, saved, .. } => {
+            *replay = Replay::new(rng.seed, *difficulty, player_count.0, game_config.checksum());
+            *saved = false;
+        }
+        ReplayMode::Playback { replay, tick } => {
+            if replay.config != game_config.checksum() {
+                eprintln!("The replay was recorded with a different game config, it may not play out the same way");
+            }
+            *tick = 0;
+        }
+    }
+}
+
+pub fn record_player_input(mut replay_mode: ResMut<ReplayMode>, player_query: Query<(&Player, &PlayerInput)>) {
+    if let ReplayMode::Record { replay, .. } = &mut *replay_mode {
+        let mut inputs = vec![PlayerInput::default(); replay.players];
+        for (player, player_input) in player_query.iter() {
+            inputs[player.index] = *player_input;
+        }
+        replay.ticks.push(inputs);
+    }
+}
+
+pub fn play_back_player_input(
+    mut replay_mode: ResMut<ReplayMode>,
+    mut player_query: Query<(&Player, &mut PlayerInput)>,
+) {
+    if let ReplayMode::Playback { replay, tick } = &mut *replay_mode {
+        let inputs = replay.ticks.get(*tick);
+        for (player, mut player_input) in player_query.iter_mut() {
+            *player_input = inputs
+                .and_then(|inputs| inputs.get(player.index))
+                .copied()
+                .unwrap_or_default();
+        }
+        *tick += 1;
+    }
+}
+
+pub fn save_recording(mut replay_mode: ResMut<ReplayMode>) {
+    if let ReplayMode::Record { path, replay, saved } = &mut *replay_mode {
+        if *saved || replay.ticks.is_empty() {
+            return;
+        }
+        match replay.save(path) {
+            Ok(()) => eprintln!("Saved replay of {} ticks to {}", replay.ticks.len(), path.display()),
+            Err(error) => eprintln!("Could not save replay to {}: {}", path.display(), error),
+        }
+        *saved = true;
+    }
+}
+
+/// Keeps the recording of a run that was still going when the window was closed.
+pub fn save_recording_on_exit(exit_events: EventReader<AppExit>, replay_mode: ResMut<ReplayMode>) {
+    if !exit_events.is_empty() {
+        save_recording(replay_mode);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replay_round_trips_through_file() {
+        let path = std::env::temp_dir().join(format!("ball-game-replay-{}.ron", std::process::id()));
+        let mut replay = Replay::new(42, Difficulty::Hard, 2, GameConfig::default().checksum());
+        replay.ticks = [Vec2::ZERO, Vec2::NEG_X, Vec2::new(-0.5, 0.25), Vec2::NEG_Y]
+            .into_iter()
+            .map(|movement| vec![PlayerInput::from_movement(movement), PlayerInput::from_movement(-movement)])
+            .collect();
+        replay.ticks[1][0] = replay.ticks[1][0].with_fire(Vec2::new(0.6, -0.8));
+
+        replay.save(&path).unwrap();
+        let loaded = Replay::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded, replay);
+    }
+
+    #[test]
+    fn replay_with_unknown_version_is_rejected() {
+        let path = std::env::temp_dir().join(format!("ball-game-replay-version-{}.ron", std::process::id()));
+        fs::write(&path, "(version: 99, seed: 1, difficulty: Normal, players: 1, config: 0, ticks: [])").unwrap();
+
+        let result = Replay::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert!(matches!(result, Err(ReplayError::Version(99))));
+    }
+
+    #[test]
+    fn a_replay_that_does_not_load_is_an_argument_error() {
+        let path = std::env::temp_dir().join(format!("ball-game-replay-missing-{}.ron", std::process::id()));
+        let error = ReplayMode::from_arg(path.to_str(), None).unwrap_err();
+
+        assert_eq!(error.name, "--replay");
+        assert!(matches!(ReplayMode::from_arg(None, Some("run.ron")), Ok(ReplayMode::Record { .. })));
+        assert!(matches!(ReplayMode::from_arg(None, None), Ok(ReplayMode::Off)));
+    }
+}
@@ -0,0 +1,301 @@
+//! Snapshots of everything the simulation of a run depends on, to put the game back to an earlier
+//! tick and to compare the state of two instances of it.
+//!
+//! Cosmetic state such as sprite colors, particles and the survival time is left out, it does not
+//! change how the run plays out.
+
+use bevy::prelude::*;
+
+use crate::checksum::Checksum;
+use crate::config::GameConfig;
+use crate::enemy::{self, EnemyBehaviour, EnemyClock, EnemySpawner};
+use crate::player::{self, Invulnerable, Knockback};
+use crate::powerup::{self, ActivePowerUps, PowerUpKind, PowerUpSpawnTimer};
+use crate::projectile::{self, Gun, ProjectilePool};
+use crate::{
+    star_bundle, Enemy, GameEntityFilter, GameRng, Lives, Player, PlayerInput, PowerUp, Projectile, Score, Star,
+    StarSpawnTimer,
+};
+
+/// The state of a run at the start of a fixed tick.
+#[derive(Clone)]
+pub struct GameSnapshot {
+    players: Vec<PlayerSnapshot>,
+    enemies: Vec<EnemySnapshot>,
+    stars: Vec<Vec3>,
+    power_ups: Vec<(Vec3, PowerUp)>,
+    /// Only the projectiles in flight, the rest are in the pool.
+    projectiles: Vec<(Vec3, Projectile)>,
+    rng: GameRng,
+    score: Score,
+    star_spawn_timer: Timer,
+    power_up_spawn_timer: Timer,
+    enemy_spawner: EnemySpawner,
+    enemy_clock: Time<EnemyClock>,
+}
+
+#[derive(Clone)]
+struct PlayerSnapshot {
+    player: Player,
+    transform: Transform,
+    input: PlayerInput,
+    lives: Lives,
+    power_ups: Option<ActivePowerUps>,
+    gun: Option<Gun>,
+    invulnerable: Option<Timer>,
+    knockback: Option<Vec2>,
+}
+
+#[derive(Clone)]
+struct EnemySnapshot {
+    transform: Transform,
+    enemy: Enemy,
+    behaviour: Option<EnemyBehaviour>,
+}
+
+impl GameSnapshot {
+    pub fn save(world: &mut World) -> GameSnapshot {
+        let players = world
+            .query::<(
+                &Player,
+                &Transform,
+                &PlayerInput,
+                &Lives,
+                Option<&ActivePowerUps>,
+                Option<&Gun>,
+                Option<&Invulnerable>,
+                Option<&Knockback>,
+            )>()
+            .iter(world)
+            .map(|(player, transform, input, lives, power_ups, gun, invulnerable, knockback)| PlayerSnapshot {
+                player: *player,
+                transform: *transform,
+                input: *input,
+                lives: *lives,
+                power_ups: power_ups.cloned(),
+                gun: gun.cloned(),
+                invulnerable: invulnerable.map(|invulnerable| invulnerable.timer.clone()),
+                knockback: knockback.map(|knockback| knockback.velocity),
+            })
+            .collect();
+        let enemies = world
+            .query::<(&Transform, &Enemy, Option<&EnemyBehaviour>)>()
+            .iter(world)
+            .map(|(transform, enemy, behaviour)| EnemySnapshot {
+                transform: *transform,
+                enemy: enemy.clone(),
+                behaviour: behaviour.copied(),
+            })
+            .collect();
+        let stars = world
+            .query_filtered::<&Transform, With<Star>>()
+            .iter(world)
+            .map(|transform| transform.translation)
+            .collect();
+        let power_ups = world
+            .query::<(&Transform, &PowerUp)>()
+            .iter(world)
+            .map(|(transform, power_up)| (transform.translation, power_up.clone()))
+            .collect();
+        let projectiles = world
+            .query::<(&Transform, &Projectile)>()
+            .iter(world)
+            .filter(|(_, projectile)| projectile.in_flight)
+            .map(|(transform, projectile)| (transform.translation, projectile.clone()))
+            .collect();
+
+        GameSnapshot {
+            players,
+            enemies,
+            stars,
+            power_ups,
+            projectiles,
+            rng: world.resource::<GameRng>().clone(),
+            score: world.resource::<Score>().clone(),
+            star_spawn_timer: world.resource::<StarSpawnTimer>().timer.clone(),
+            power_up_spawn_timer: world.resource::<PowerUpSpawnTimer>().timer.clone(),
+            enemy_spawner: world.resource::<EnemySpawner>().clone(),
+            enemy_clock: *world.resource::<Time<EnemyClock>>(),
+        }
+    }
+
+    /// Puts the run back the way it was. Every player, enemy, star and power-up is spawned again,
+    /// so entities are not the same as before and may come in a different order.
+    pub fn restore(&self, world: &mut World) {
+        let game_entities: Vec<Entity> = world.query_filtered::<Entity, GameEntityFilter>().iter(world).collect();
+        for entity in game_entities {
+            world.despawn(entity);
+        }
+
+        let asset_server = world.resource::<AssetServer>().clone();
+        let game_config = world.resource::<GameConfig>().clone();
+        for snapshot in &self.players {
+            let mut entity =
+                world.spawn(player::player_bundle(&asset_server, &game_config, snapshot.player, snapshot.lives));
+            entity.insert((snapshot.transform, snapshot.input));
+            if let Some(power_ups) = &snapshot.power_ups {
+                entity.insert(power_ups.clone());
+            }
+            if let Some(gun) = &snapshot.gun {
+                entity.insert(gun.clone());
+            }
+            if let Some(timer) = &snapshot.invulnerable {
+                entity.insert(Invulnerable { timer: timer.clone() });
+            }
+            if let Some(velocity) = snapshot.knockback {
+                entity.insert(Knockback { velocity });
+            }
+        }
+        for snapshot in &self.enemies {
+            let behaviour = snapshot.behaviour.unwrap_or(EnemyBehaviour::Bouncer);
+            let Enemy { direction, speed } = snapshot.enemy;
+            let bundle =
+                enemy::enemy_bundle(&asset_server, &game_config, snapshot.transform, direction, speed, behaviour);
+            let mut entity = world.spawn(bundle);
+            if snapshot.behaviour.is_none() {
+                entity.remove::<EnemyBehaviour>();
+            }
+        }
+        for &position in &self.stars {
+            world.spawn(star_bundle(&asset_server, position));
+        }
+        for (position, power_up) in &self.power_ups {
+            world.spawn(powerup::power_up_bundle(power_up.clone(), *position));
+        }
+        self.restore_projectiles(world, &asset_server);
+
+        world.insert_resource(self.rng.clone());
+        world.insert_resource(self.score.clone());
+        world.resource_mut::<StarSpawnTimer>().timer = self.star_spawn_timer.clone();
+        world.resource_mut::<PowerUpSpawnTimer>().timer = self.power_up_spawn_timer.clone();
+        world.insert_resource(self.enemy_spawner.clone());
+        world.insert_resource(self.enemy_clock);
+    }
+
+    fn restore_projectiles(&self, world: &mut World, asset_server: &AssetServer) {
+        world.resource_scope(|world, mut pool: Mut<ProjectilePool>| {
+            let mut projectile_query = world.query::<(Entity, &mut Projectile, &mut Transform, &mut Visibility)>();
+            for (entity, mut projectile, _, mut visibility) in projectile_query.iter_mut(world) {
+                pool.release(entity, &mut projectile, &mut visibility);
+            }
+            for (position, fired) in &self.projectiles {
+                match pool.free.pop() {
+                    Some(entity) => {
+                        if let Ok((_, mut projectile, mut transform, mut visibility)) =
+                            projectile_query.get_mut(world, entity)
+                        {
+                            *projectile = fired.clone();
+                            transform.translation = *position;
+                            *visibility = Visibility::Inherited;
+                        }
+                    }
+                    None => {
+                        world.spawn(projectile::projectile_bundle(asset_server, fired.clone(), position.truncate()));
+                    }
+                }
+            }
+        });
+    }
+
+    /// A checksum of the state, equal on two instances that simulated the same run, whatever
+    /// machine or build they run on.
+    pub fn checksum(&self) -> u64 {
+        // Entities are summed rather than hashed in turn, so the checksum does not depend on their
+        // order, which differs between instances once one of them has restored a snapshot.
+        let mut entities = 0u64;
+        for snapshot in &self.players {
+            entities = entities.wrapping_add(checksum_with(|checksum| {
+                checksum.u64(snapshot.player.index as u64);
+                checksum.vec3(snapshot.transform.translation);
+                checksum.vec3(snapshot.transform.scale);
+                let input = snapshot.input;
+                checksum.bytes(&[input.x, input.y, input.aim_x, input.aim_y].map(|axis| axis as u8));
+                checksum.u32(snapshot.lives.remaining);
+                if let Some(power_ups) = &snapshot.power_ups {
+                    for kind in PowerUpKind::ALL {
+                        match power_ups.remaining(kind) {
+                            Some(remaining) => {
+                                checksum.u32(1);
+                                checksum.duration(remaining);
+                            }
+                            None => checksum.u32(0),
+                        }
+                    }
+                }
+                if let Some(gun) = &snapshot.gun {
+                    checksum_timer(checksum, &gun.cooldown);
+                }
+                if let Some(timer) = &snapshot.invulnerable {
+                    checksum_timer(checksum, timer);
+                }
+                if let Some(velocity) = snapshot.knockback {
+                    checksum.vec2(velocity);
+                }
+            }));
+        }
+        for snapshot in &self.enemies {
+            entities = entities.wrapping_add(checksum_with(|checksum| {
+                checksum.vec3(snapshot.transform.translation);
+                checksum.vec3(snapshot.transform.scale);
+                checksum.vec2(snapshot.enemy.direction);
+                checksum.f32(snapshot.enemy.speed);
+                match snapshot.behaviour {
+                    None | Some(EnemyBehaviour::Bouncer) => checksum.u32(0),
+                    Some(EnemyBehaviour::Chaser) => checksum.u32(1),
+                    Some(EnemyBehaviour::Wanderer { noise_time }) => {
+                        checksum.u32(2);
+                        checksum.f32(noise_time);
+                    }
+                    Some(EnemyBehaviour::Splitter { bounces_left }) => {
+                        checksum.u32(3);
+                        checksum.u32(bounces_left);
+                    }
+                }
+            }));
+        }
+        for &position in &self.stars {
+            entities = entities.wrapping_add(checksum_with(|checksum| checksum.vec3(position)));
+        }
+        for (position, power_up) in &self.power_ups {
+            entities = entities.wrapping_add(checksum_with(|checksum| {
+                checksum.vec3(*position);
+                checksum.u32(power_up.kind as u32);
+                checksum_timer(checksum, &power_up.lifetime);
+            }));
+        }
+        for (position, projectile) in &self.projectiles {
+            entities = entities.wrapping_add(checksum_with(|checksum| {
+                checksum.vec3(*position);
+                checksum.vec2(projectile.velocity);
+                checksum.u64(projectile.owner as u64);
+            }));
+        }
+
+        checksum_with(|checksum| {
+            checksum.u64(entities);
+            checksum.u64(self.rng.seed);
+            checksum.bytes(&self.rng.rng.get_word_pos().to_le_bytes());
+            checksum.u32(self.score.value);
+            for points in self.score.players {
+                checksum.u32(points);
+            }
+            checksum_timer(checksum, &self.star_spawn_timer);
+            checksum_timer(checksum, &self.power_up_spawn_timer);
+            checksum_timer(checksum, &self.enemy_spawner.timer);
+            checksum.f32(self.enemy_spawner.speed);
+            checksum.f32(self.enemy_clock.context().relative_speed);
+        })
+    }
+}
+
+fn checksum_with(write: impl FnOnce(&mut Checksum)) -> u64 {
+    let mut checksum = Checksum::default();
+    write(&mut checksum);
+    checksum.finish()
+}
+
+fn checksum_timer(checksum: &mut Checksum, timer: &Timer) {
+    checksum.duration(timer.elapsed());
+    checksum.duration(timer.duration());
+    checksum.u32(timer.times_finished_this_tick());
+}
@@ -0,0 +1,1111 @@
+use std::net::UdpSocket;
+use std::time::Duration;
+
+use bevy::ecs::system::RunSystemOnce;
+use bevy::input::gamepad::{
+    GamepadConnection, GamepadConnectionEvent, RawGamepadAxisChangedEvent, RawGamepadButtonChangedEvent,
+    RawGamepadEvent,
+};
+use bevy::input::InputPlugin;
+use bevy::state::app::StatesPlugin;
+use bevy::time::TimeUpdateStrategy;
+use bevy::window::PrimaryWindow;
+
+use super::*;
+use crate::audio::Volumes;
+use crate::config::EnemyWeights;
+use crate::enemy::{enemy_movement, sweep_circle_in_bounds, EnemyBehaviour, EnemyClock, EnemySpawnConfig};
+use crate::input::{apply_deadzone, Binding};
+use crate::netplay::{NetplaySession, MAX_PREDICTION};
+use crate::particles::Particles;
+use crate::powerup::{ActivePowerUps, PowerUpKind};
+use crate::replay::{Replay, ReplayMode};
+use crate::snapshot::GameSnapshot;
+
+const MIN: Vec2 = Vec2::new(-100.0, -100.0);
+const MAX: Vec2 = Vec2::new(100.0, 100.0);
+
+#[test]
+fn sweep_inside_bounds_moves_freely() {
+    let (position, velocity, hit_wall) =
+        sweep_circle_in_bounds(Vec2::ZERO, Vec2::new(10.0, -20.0), 1.0, MIN, MAX);
+
+    assert_eq!(position, Vec2::new(10.0, -20.0));
+    assert_eq!(velocity, Vec2::new(10.0, -20.0));
+    assert_eq!(hit_wall, None);
+}
+
+#[test]
+fn sweep_reflects_off_wall() {
+    let (position, velocity, hit_wall) =
+        sweep_circle_in_bounds(Vec2::new(95.0, 0.0), Vec2::new(10.0, 0.0), 1.0, MIN, MAX);
+
+    assert_eq!(position, Vec2::new(95.0, 0.0));
+    assert_eq!(velocity, Vec2::new(-10.0, 0.0));
+    assert_eq!(hit_wall, Some(WallSide::Right));
+}
+
+#[test]
+fn sweep_outside_bounds_heads_back_in() {
+    // Already past the wall and moving away from the arena, the old toggle logic could flip
+    // the direction back and forth here.
+    let (position, velocity, _) =
+        sweep_circle_in_bounds(Vec2::new(150.0, -150.0), Vec2::new(10.0, -10.0), 0.1, MIN, MAX);
+    assert!(position.x <= MAX.x && position.y >= MIN.y);
+    assert!(velocity.x < 0.0 && velocity.y > 0.0);
+
+    // Already past the wall but moving back in, the direction must be kept.
+    let (_, velocity, _) =
+        sweep_circle_in_bounds(Vec2::new(150.0, 0.0), Vec2::new(-10.0, 0.0), 0.1, MIN, MAX);
+    assert!(velocity.x < 0.0);
+}
+
+#[test]
+fn sweep_large_step_stays_inside() {
+    let (position, _, _) = sweep_circle_in_bounds(Vec2::ZERO, Vec2::new(10_000.0, 0.0), 1.0, MIN, MAX);
+
+    assert!(position.x >= MIN.x && position.x <= MAX.x);
+}
+
+#[test]
+fn enemy_outside_window_comes_back() {
+    let x_max = ARENA_WIDTH / 2.0 - GameConfig::default().enemy_size / 2.0;
+    let (mut world, enemy) = enemy_movement_world(Vec3::new(x_max + 50.0, 0.0, 0.0), Vec2::X);
+
+    for _ in 0..10 {
+        world.run_system_once(enemy_movement).unwrap();
+        let translation = world.get::<Transform>(enemy).unwrap().translation;
+        assert!(translation.x <= x_max, "enemy stuck outside the arena at {}", translation.x);
+    }
+    assert!(world.get::<Enemy>(enemy).unwrap().direction.x < 0.0);
+}
+
+#[test]
+fn wall_collision_reports_enemy_side_and_contact() {
+    let half_width = ARENA_WIDTH / 2.0;
+    let x_min = -half_width + GameConfig::default().enemy_size / 2.0;
+    let (mut world, enemy) = enemy_movement_world(Vec3::new(x_min + 1.0, 20.0, 0.0), Vec2::NEG_X);
+
+    world.run_system_once(enemy_movement).unwrap();
+
+    let events: Vec<_> = world.resource_mut::<Events<EnemyWallCollisionEvent>>().drain().collect();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].enemy, enemy);
+    assert_eq!(events[0].side, WallSide::Left);
+    assert_eq!(events[0].contact, Vec2::new(-half_width, 20.0));
+}
+
+const ARENA_WIDTH: f32 = arena::ARENA_SIZE.x;
+const ARENA_HEIGHT: f32 = arena::ARENA_SIZE.y;
+// Deliberately not the arena size, the arena must not depend on the window.
+const WINDOW_WIDTH: f32 = 800.0;
+const WINDOW_HEIGHT: f32 = 600.0;
+
+/// The game running under `MinimalPlugins` with a synthetic window and a clock that advances by
+/// exactly one fixed timestep per update, so it needs no GPU or display.
+struct Harness {
+    app: App,
+}
+
+impl Harness {
+    fn new() -> Harness {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, StatesPlugin, AssetPlugin::default(), InputPlugin))
+            .init_asset::<Image>()
+            .init_asset::<AudioSource>()
+            .insert_resource(TimeUpdateStrategy::ManualDuration(Time::<Fixed>::default().timestep()))
+            .insert_resource(GameRng::new(0));
+        app.add_plugins(GamePlugin);
+
+        app.world_mut().spawn((
+            Window {
+                resolution: (WINDOW_WIDTH, WINDOW_HEIGHT).into(),
+                ..default()
+            },
+            PrimaryWindow,
+        ));
+
+        let mut harness = Harness { app };
+        // The first update only initialises the clock.
+        harness.app.update();
+        harness
+    }
+
+    /// Starts a run with no enemies, so each test can place exactly the enemies it needs.
+    fn start_game(&mut self) {
+        self.app.world_mut().resource_mut::<EnemySpawnConfig>().max_enemies = 0;
+        self.app.world_mut().resource_mut::<NextState<GameState>>().set(GameState::InGame);
+        self.app.update();
+
+        let enemies: Vec<Entity> = self
+            .app
+            .world_mut()
+            .query_filtered::<Entity, With<Enemy>>()
+            .iter(self.app.world())
+            .collect();
+        for enemy in enemies {
+            self.app.world_mut().despawn(enemy);
+        }
+    }
+
+    fn set_lives(&mut self, player: Entity, remaining: u32) {
+        self.app.world_mut().entity_mut(player).insert(Lives { remaining });
+    }
+
+    fn lives(&self, player: Entity) -> u32 {
+        self.app.world().get::<Lives>(player).unwrap().remaining
+    }
+
+    fn tick(&mut self, ticks: usize) {
+        for _ in 0..ticks {
+            self.app.update();
+        }
+    }
+
+    /// Starts a two-player co-op run with no enemies.
+    fn start_coop_game(&mut self) {
+        self.app.insert_resource(PlayerCount(2));
+        self.start_game();
+    }
+
+    /// The players still in the run, by index.
+    fn players(&mut self) -> Vec<(usize, Entity)> {
+        let mut players: Vec<(usize, Entity)> = self
+            .app
+            .world_mut()
+            .query::<(Entity, &Player)>()
+            .iter(self.app.world())
+            .map(|(entity, player)| (player.index, entity))
+            .collect();
+        players.sort();
+        players
+    }
+
+    fn player(&mut self) -> Option<Entity> {
+        self.app
+            .world_mut()
+            .query_filtered::<Entity, With<Player>>()
+            .get_single(self.app.world())
+            .ok()
+    }
+
+    /// Plugs in a synthetic gamepad, the way a gamepad backend would.
+    fn connect_gamepad(&mut self) -> Entity {
+        let gamepad = self.app.world_mut().spawn_empty().id();
+        self.app
+            .world_mut()
+            .send_event(GamepadConnectionEvent::new(
+                gamepad,
+                GamepadConnection::Connected {
+                    name: "Synthetic Gamepad".to_string(),
+                    vendor_id: None,
+                    product_id: None,
+                },
+            ));
+        self.app.update();
+        gamepad
+    }
+
+    fn spawn_enemy(&mut self, position: Vec3, direction: Vec2, speed: f32) -> Entity {
+        self.app
+            .world_mut()
+            .spawn((Transform::from_translation(position), Enemy { direction, speed }))
+            .id()
+    }
+
+    fn translation(&self, entity: Entity) -> Vec3 {
+        self.app.world().get::<Transform>(entity).unwrap().translation
+    }
+
+    fn state(&self) -> GameState {
+        *self.app.world().resource::<State<GameState>>().get()
+    }
+
+    fn session(&self) -> &NetplaySession {
+        self.app.world().resource::<NetplaySession>()
+    }
+}
+
+/// A bare world with only what `enemy_movement` needs: the arena, one fixed tick on the enemy
+/// clock and a single enemy at `position` heading in `direction` at the default speed.
+fn enemy_movement_world(position: Vec3, direction: Vec2) -> (World, Entity) {
+    let mut world = World::new();
+    world.init_resource::<Events<EnemyWallCollisionEvent>>();
+    world.init_resource::<GameConfig>();
+    world.init_resource::<ArenaBounds>();
+    let mut time = Time::<EnemyClock>::default();
+    time.advance_by(Time::<Fixed>::default().timestep());
+    world.insert_resource(time);
+
+    let enemy = world
+        .spawn((
+            Transform::from_translation(position),
+            Enemy {
+                direction,
+                speed: GameConfig::default().enemy_speed,
+            },
+        ))
+        .id();
+    (world, enemy)
+}
+
+#[test]
+fn headless_game_starts_a_run() {
+    let mut harness = Harness::new();
+    assert_eq!(harness.state(), GameState::MainMenu);
+
+    harness.start_game();
+
+    assert_eq!(harness.state(), GameState::InGame);
+    assert!(harness.player().is_some());
+}
+
+#[test]
+fn stars_stop_spawning_once_the_arena_is_full() {
+    let mut harness = Harness::new();
+    harness.start_game();
+    let stars = harness.app.world_mut().query_filtered::<(), With<Star>>().iter(harness.app.world()).count();
+    for _ in stars..MAX_STARS {
+        harness.app.world_mut().spawn((Transform::from_xyz(500.0, 300.0, 0.0), Star {}));
+    }
+
+    harness.tick(3 * 64);
+
+    let stars = harness.app.world_mut().query_filtered::<(), With<Star>>().iter(harness.app.world()).count();
+    assert_eq!(stars, MAX_STARS);
+}
+
+#[test]
+fn player_is_confined_to_the_arena() {
+    let mut harness = Harness::new();
+    harness.start_game();
+    let player = harness.player().unwrap();
+
+    let mut keyboard_input = harness.app.world_mut().resource_mut::<ButtonInput<KeyCode>>();
+    keyboard_input.press(KeyCode::ArrowRight);
+    keyboard_input.press(KeyCode::ArrowUp);
+    harness.tick(200);
+
+    let translation = harness.translation(player);
+    assert_eq!(translation.x, ARENA_WIDTH / 2.0 - GameConfig::default().player_size / 2.0);
+    assert_eq!(translation.y, ARENA_HEIGHT / 2.0 - GameConfig::default().player_size / 2.0);
+}
+
+#[test]
+fn arena_has_a_fixed_logical_size() {
+    let mut harness = Harness::new();
+    harness.start_game();
+
+    assert_eq!(
+        *harness.app.world().resource::<ArenaBounds>(),
+        ArenaBounds::from_size(ARENA_WIDTH, ARENA_HEIGHT)
+    );
+    let window = harness
+        .app
+        .world_mut()
+        .query_filtered::<&mut Window, With<PrimaryWindow>>()
+        .single_mut(harness.app.world_mut())
+        .into_inner();
+    window.resolution.set(400.0, 300.0);
+    harness.tick(1);
+
+    assert_eq!(
+        *harness.app.world().resource::<ArenaBounds>(),
+        ArenaBounds::from_size(ARENA_WIDTH, ARENA_HEIGHT)
+    );
+}
+
+#[test]
+fn enemy_bounces_off_the_wall() {
+    let mut harness = Harness::new();
+    harness.start_game();
+    let player = harness.player().unwrap();
+    harness.app.world_mut().despawn(player);
+
+    let x_max = ARENA_WIDTH / 2.0 - GameConfig::default().enemy_size / 2.0;
+    let enemy = harness.spawn_enemy(Vec3::new(x_max - 1.0, 0.0, 0.0), Vec2::X, GameConfig::default().enemy_speed);
+    // The enemies despawned by `start_game` may have hit a wall on their first tick.
+    harness.app.world_mut().resource_mut::<Particles>().particles.clear();
+
+    harness.tick(1);
+    assert!(harness.app.world().get::<Enemy>(enemy).unwrap().direction.x < 0.0);
+    let particles = &harness.app.world().resource::<Particles>().particles;
+    assert!(!particles.is_empty());
+    assert!(particles.iter().all(|particle| particle.effect == ParticleEffect::WallHit && particle.velocity.x < 0.0));
+
+    let before = harness.translation(enemy).x;
+    harness.tick(10);
+    let after = harness.translation(enemy).x;
+    assert!(after < before && after <= x_max);
+}
+
+#[test]
+fn chaser_turns_toward_the_player_at_a_limited_rate() {
+    let mut harness = Harness::new();
+    harness.start_game();
+    let player = harness.player().unwrap();
+    let player_position = harness.translation(player).truncate();
+
+    let enemy = harness.spawn_enemy((player_position + Vec2::new(0.0, 300.0)).extend(0.0), Vec2::X, 0.0);
+    harness.app.world_mut().entity_mut(enemy).insert(EnemyBehaviour::Chaser);
+    harness.tick(1);
+
+    let max_turn = GameConfig::default().chaser_turn_rate / 64.0;
+    let direction = harness.app.world().get::<Enemy>(enemy).unwrap().direction;
+    assert!((Vec2::X.angle_to(direction).abs() - max_turn).abs() < 1e-4);
+
+    harness.tick(128);
+    let direction = harness.app.world().get::<Enemy>(enemy).unwrap().direction;
+    assert!(direction.dot(Vec2::NEG_Y) > 0.99, "{:?}", direction);
+}
+
+#[test]
+fn splitter_breaks_in_two_after_its_bounces() {
+    let mut harness = Harness::new();
+    harness.start_game();
+    let player = harness.player().unwrap();
+    harness.app.world_mut().despawn(player);
+
+    let x_max = ARENA_WIDTH / 2.0 - GameConfig::default().enemy_size / 2.0;
+    let splitter = harness.spawn_enemy(Vec3::new(x_max - 1.0, 0.0, 0.0), Vec2::X, GameConfig::default().enemy_speed);
+    harness.app.world_mut().entity_mut(splitter).insert(EnemyBehaviour::Splitter { bounces_left: 2 });
+
+    harness.tick(1);
+    assert_eq!(
+        harness.app.world().get::<EnemyBehaviour>(splitter),
+        Some(&EnemyBehaviour::Splitter { bounces_left: 1 })
+    );
+
+    // Across the arena and back to the left wall.
+    let ticks = (ARENA_WIDTH / GameConfig::default().enemy_speed * 64.0) as usize;
+    harness.tick(ticks);
+    assert!(harness.app.world().get_entity(splitter).is_err());
+
+    let halves: Vec<(Vec3, EnemyBehaviour)> = harness
+        .app
+        .world_mut()
+        .query::<(&Transform, &EnemyBehaviour)>()
+        .iter(harness.app.world())
+        .map(|(transform, behaviour)| (transform.scale, *behaviour))
+        .collect();
+    assert_eq!(halves.len(), 2);
+    for (scale, behaviour) in halves {
+        assert_eq!(scale.x, enemy::SPLIT_SCALE);
+        assert_eq!(behaviour, EnemyBehaviour::Bouncer);
+    }
+}
+
+#[test]
+fn enemy_mix_only_picks_weighted_behaviours() {
+    let game_config = GameConfig::default();
+    let mut rng = GameRng::new(0);
+    let weights = EnemyWeights {
+        bouncer: 0,
+        chaser: 1,
+        wanderer: 1,
+        splitter: 0,
+    };
+    for _ in 0..100 {
+        let behaviour = EnemyBehaviour::random(&mut *rng, &weights, &game_config);
+        assert!(matches!(behaviour, EnemyBehaviour::Chaser | EnemyBehaviour::Wanderer { .. }));
+    }
+
+    let none = EnemyWeights {
+        chaser: 0,
+        wanderer: 0,
+        ..weights
+    };
+    assert_eq!(EnemyBehaviour::random(&mut *rng, &none, &game_config), EnemyBehaviour::Bouncer);
+}
+
+#[test]
+fn wander_noise_is_smooth_and_bounded() {
+    let mut previous = enemy::value_noise(0.0);
+    for step in 1..1000 {
+        let value = enemy::value_noise(step as f32 * 0.01);
+        assert!((-1.0..=1.0).contains(&value));
+        assert!((value - previous).abs() < 0.05);
+        previous = value;
+    }
+}
+
+#[test]
+fn enemy_hitting_player_on_the_last_life_ends_the_game() {
+    let mut harness = Harness::new();
+    harness.start_game();
+    let player = harness.player().unwrap();
+    harness.set_lives(player, 1);
+
+    let position = harness.translation(player);
+    harness.spawn_enemy(position, Vec2::X, 0.0);
+    harness.tick(2);
+
+    assert!(harness.player().is_none());
+    assert_eq!(harness.state(), GameState::GameOver);
+}
+
+#[test]
+fn a_hit_costs_one_life_and_grants_invulnerability() {
+    let mut harness = Harness::new();
+    harness.start_game();
+    let player = harness.player().unwrap();
+    assert_eq!(harness.lives(player), GameConfig::default().player_lives);
+
+    let position = harness.translation(player);
+    let enemy = harness.spawn_enemy(position + Vec3::X, Vec2::X, 0.0);
+    harness.tick(2);
+
+    // One life lost, the player is pushed away from the enemy and blinks.
+    assert_eq!(harness.lives(player), 2);
+    assert_eq!(harness.state(), GameState::InGame);
+    assert!(harness.app.world().get::<player::Invulnerable>(player).is_some());
+    assert!(harness.translation(player).x < position.x);
+
+    // Touching the enemy again while invulnerable costs nothing.
+    let position = harness.translation(player);
+    harness.app.world_mut().get_mut::<Transform>(enemy).unwrap().translation = position;
+    harness.tick(10);
+    assert_eq!(harness.lives(player), 2);
+
+    // Once the invulnerability is over the next touch costs another life.
+    let ticks = (GameConfig::default().invulnerability_time * 64.0) as usize;
+    harness.tick(ticks);
+    let position = harness.translation(player);
+    harness.app.world_mut().get_mut::<Transform>(enemy).unwrap().translation = position;
+    harness.tick(2);
+    assert_eq!(harness.lives(player), 1);
+}
+
+fn spawn_power_up(harness: &mut Harness, kind: PowerUpKind, position: Vec3) {
+    harness.app.world_mut().spawn((
+        Transform::from_translation(position),
+        PowerUp {
+            kind,
+            lifetime: Timer::from_seconds(powerup::POWER_UP_LIFETIME, TimerMode::Once),
+        },
+    ));
+}
+
+#[test]
+fn a_shrunk_player_collects_stars_and_meets_walls_at_its_own_size() {
+    let mut harness = Harness::new();
+    harness.start_game();
+    let player = harness.player().unwrap();
+    let position = harness.translation(player);
+    spawn_power_up(&mut harness, PowerUpKind::Shrink, position);
+    harness.tick(1);
+
+    let player_size = GameConfig::default().player_size * powerup::SHRINK_SCALE;
+    let out_of_reach = position + Vec3::new(0.0, player_size / 2.0 + STAR_SIZE / 2.0 + 1.0, 0.0);
+    let star = harness.app.world_mut().spawn((Transform::from_translation(out_of_reach), Star {})).id();
+    harness.tick(1);
+    assert!(harness.app.world().get_entity(star).is_ok());
+
+    harness.app.world_mut().resource_mut::<ButtonInput<KeyCode>>().press(KeyCode::KeyD);
+    harness.tick(200);
+    assert_eq!(harness.translation(player).x, ARENA_WIDTH / 2.0 - player_size / 2.0);
+}
+
+#[test]
+fn a_shield_takes_the_hit_instead_of_a_life() {
+    let mut harness = Harness::new();
+    harness.start_game();
+    let player = harness.player().unwrap();
+    let position = harness.translation(player);
+    spawn_power_up(&mut harness, PowerUpKind::Shield, position);
+    harness.tick(1);
+    assert!(harness.app.world().get::<ActivePowerUps>(player).unwrap().is_active(PowerUpKind::Shield));
+
+    let enemy = harness.spawn_enemy(position + Vec3::X, Vec2::X, 0.0);
+    harness.tick(2);
+
+    assert_eq!(harness.lives(player), GameConfig::default().player_lives);
+    assert!(!harness.app.world().get::<ActivePowerUps>(player).unwrap().is_active(PowerUpKind::Shield));
+    assert!(harness.app.world().get::<player::Invulnerable>(player).is_none());
+    assert!(harness.app.world().get::<player::Knockback>(player).is_none());
+    assert!(harness.app.world().get_entity(enemy).is_err());
+
+    harness.tick(10);
+    assert_eq!(harness.lives(player), GameConfig::default().player_lives);
+}
+
+#[test]
+fn slow_motion_slows_the_enemies() {
+    let enemy_travel = |slow_motion: bool| {
+        let mut harness = Harness::new();
+        harness.start_game();
+        let player = harness.player().unwrap();
+        if slow_motion {
+            let position = harness.translation(player);
+            spawn_power_up(&mut harness, PowerUpKind::SlowMotion, position);
+        }
+        // Picked up on this tick, slowing the enemies from the next one.
+        harness.tick(1);
+        let enemy = harness.spawn_enemy(Vec3::new(0.0, 200.0, 0.0), Vec2::X, 100.0);
+        harness.tick(32);
+        harness.translation(enemy).x
+    };
+
+    let normal = enemy_travel(false);
+    let slow = enemy_travel(true);
+    assert!((slow - normal * powerup::SLOW_MOTION_SPEED).abs() < 1.0, "{} vs {}", slow, normal);
+}
+
+#[test]
+fn power_ups_run_out() {
+    let mut harness = Harness::new();
+    harness.start_game();
+    let player = harness.player().unwrap();
+    let position = harness.translation(player);
+    spawn_power_up(&mut harness, PowerUpKind::Shrink, position);
+    harness.tick(2);
+    assert_eq!(harness.app.world().get::<Transform>(player).unwrap().scale.x, powerup::SHRINK_SCALE);
+
+    harness.tick((PowerUpKind::Shrink.duration() * 64.0) as usize + 1);
+    assert!(!harness.app.world().get::<ActivePowerUps>(player).unwrap().is_active(PowerUpKind::Shrink));
+    assert_eq!(harness.app.world().get::<Transform>(player).unwrap().scale, Vec3::ONE);
+}
+
+#[test]
+fn game_over_explodes_once_and_freezes_the_arena() {
+    let mut harness = Harness::new();
+    harness.start_game();
+    harness.app.world_mut().resource_mut::<Score>().value = 7;
+    let player = harness.player().unwrap();
+    harness.set_lives(player, 1);
+
+    let position = harness.translation(player);
+    let enemies: Vec<Entity> = (0..3).map(|_| harness.spawn_enemy(position, Vec2::X, 100.0)).collect();
+    let mut sfx_cursor = harness.app.world().resource::<Events<PlaySfx>>().get_cursor();
+    let mut explosions = 0;
+    for _ in 0..2 {
+        harness.tick(1);
+        let events = harness.app.world().resource::<Events<PlaySfx>>();
+        explosions += sfx_cursor.read(events).filter(|event| event.sfx == Sfx::Explosion).count();
+    }
+    assert_eq!(explosions, 1);
+    assert_eq!(harness.state(), GameState::GameOver);
+    let particles = &harness.app.world().resource::<Particles>().particles;
+    assert!(particles.iter().any(|particle| particle.effect == ParticleEffect::PlayerDeath));
+
+    let frozen = harness.translation(enemies[0]);
+    harness.tick(10);
+    assert_eq!(harness.translation(enemies[0]), frozen);
+
+    let overlay: Vec<String> = harness
+        .app
+        .world_mut()
+        .query::<&Text>()
+        .iter(harness.app.world())
+        .map(|text| text.0.clone())
+        .collect();
+    assert!(overlay.iter().any(|line| line == "Score: 7"), "{:?}", overlay);
+    let seed = harness.app.world().resource::<GameRng>().seed;
+    assert!(overlay.contains(&format!("Seed {}", seed)), "{:?}", overlay);
+}
+
+#[test]
+fn every_volume_channel_has_its_own_keys() {
+    let mut harness = Harness::new();
+    for key in [KeyCode::BracketLeft, KeyCode::Semicolon, KeyCode::Semicolon] {
+        harness.app.world_mut().resource_mut::<ButtonInput<KeyCode>>().press(key);
+        harness.tick(1);
+        harness.app.world_mut().resource_mut::<ButtonInput<KeyCode>>().release(key);
+        harness.tick(1);
+    }
+
+    let settings = harness.app.world().resource::<Volumes>().settings;
+    assert_eq!(settings.master, 1.0);
+    assert!((settings.sfx - 0.9).abs() < 1e-6);
+    assert!((settings.music - 0.3).abs() < 1e-6);
+}
+
+#[test]
+fn deadzone_zeroes_small_input_and_rescales_the_rest() {
+    assert_eq!(apply_deadzone(Vec2::new(0.1, 0.0), 0.2), Vec2::ZERO);
+    assert_eq!(apply_deadzone(Vec2::new(0.0, -1.0), 0.2), Vec2::new(0.0, -1.0));
+    assert!((apply_deadzone(Vec2::new(0.6, 0.0), 0.2).x - 0.5).abs() < 1e-6);
+}
+
+#[test]
+fn seed_must_be_an_unsigned_integer() {
+    assert_eq!(SeedConfig::from_value("--seed", "42").unwrap().seed, Some(42));
+    assert!(SeedConfig::from_value("--seed", "-1").is_err());
+    assert!(SeedConfig::from_value("BALL_GAME_SEED", "forty-two").is_err());
+}
+
+#[test]
+fn player_count_must_be_one_or_two() {
+    assert_eq!(PlayerCount::from_arg(None).unwrap(), PlayerCount(1));
+    assert_eq!(PlayerCount::from_arg(Some("2")).unwrap(), PlayerCount(2));
+    for players in ["0", "3", "two"] {
+        assert!(PlayerCount::from_arg(Some(players)).is_err(), "{}", players);
+    }
+}
+
+#[test]
+fn player_input_is_clamped_and_round_trips() {
+    assert_eq!(PlayerInput::from_movement(Vec2::new(3.0, 0.0)).movement(), Vec2::X);
+    assert_eq!(PlayerInput::from_movement(Vec2::ZERO), PlayerInput::default());
+    assert!(PlayerInput::from_movement(Vec2::ONE).movement().length() <= 1.0);
+}
+
+#[test]
+fn player_input_fires_only_when_aimed() {
+    let input = PlayerInput::from_movement(Vec2::X);
+    assert_eq!(input.fire_direction(), None);
+
+    let direction = input.with_fire(Vec2::new(0.0, -5.0)).fire_direction().unwrap();
+    assert!((direction - Vec2::NEG_Y).length() < 1e-6);
+}
+
+/// Connects a gamepad and pushes its right stick all the way right.
+fn aim_right(harness: &mut Harness) -> Entity {
+    let gamepad = harness.connect_gamepad();
+    harness.app.world_mut().send_event(RawGamepadEvent::Axis(RawGamepadAxisChangedEvent::new(
+        gamepad,
+        GamepadAxis::RightStickX,
+        1.0,
+    )));
+    gamepad
+}
+
+#[test]
+fn holding_fire_shoots_at_the_cooldown_rate_from_the_pool() {
+    let mut harness = Harness::new();
+    harness.start_game();
+    let gamepad = aim_right(&mut harness);
+
+    let mut sfx_cursor = harness.app.world().resource::<Events<PlaySfx>>().get_cursor();
+    let mut shots = 0;
+    for _ in 0..64 {
+        harness.tick(1);
+        let events = harness.app.world().resource::<Events<PlaySfx>>();
+        shots += sfx_cursor.read(events).filter(|event| event.sfx == Sfx::Laser).count();
+    }
+    assert_eq!(shots, (1.0 / projectile::FIRE_COOLDOWN) as usize);
+
+    // Every shot leaves the arena and goes back into the pool, which never had to grow.
+    harness.app.world_mut().send_event(RawGamepadEvent::Axis(RawGamepadAxisChangedEvent::new(
+        gamepad,
+        GamepadAxis::RightStickX,
+        0.0,
+    )));
+    harness.tick(64);
+    let projectiles: Vec<bool> = harness
+        .app
+        .world_mut()
+        .query::<&Projectile>()
+        .iter(harness.app.world())
+        .map(|projectile| projectile.in_flight)
+        .collect();
+    assert_eq!(projectiles.len(), projectile::PROJECTILE_POOL_SIZE);
+    assert!(projectiles.iter().all(|in_flight| !in_flight));
+    assert_eq!(
+        harness.app.world().resource::<projectile::ProjectilePool>().free.len(),
+        projectile::PROJECTILE_POOL_SIZE
+    );
+}
+
+#[test]
+fn a_projectile_destroys_the_enemy_it_hits_for_points() {
+    let mut harness = Harness::new();
+    harness.start_game();
+    let player = harness.player().unwrap();
+    let position = harness.translation(player);
+    let enemy = harness.spawn_enemy(position + Vec3::new(300.0, 0.0, 0.0), Vec2::Y, 0.0);
+    // A star under the player would add its point to the score, and may already have.
+    let stars: Vec<Entity> = harness
+        .app
+        .world_mut()
+        .query_filtered::<Entity, With<Star>>()
+        .iter(harness.app.world())
+        .collect();
+    for star in stars {
+        harness.app.world_mut().despawn(star);
+    }
+    let before = harness.app.world().resource::<Score>().value;
+
+    aim_right(&mut harness);
+    harness.tick(30);
+
+    assert!(harness.app.world().get_entity(enemy).is_err());
+    assert_eq!(harness.app.world().resource::<Score>().value - before, projectile::ENEMY_POINTS);
+}
+
+/// The distance the player covers in `ticks` fixed ticks while a gamepad sends `event`.
+fn gamepad_player_travel(event: impl Fn(Entity) -> RawGamepadEvent, ticks: usize) -> Vec3 {
+    let mut harness = Harness::new();
+    harness.start_game();
+    let gamepad = harness.connect_gamepad();
+    let player = harness.player().unwrap();
+    let start = harness.translation(player);
+
+    harness.app.world_mut().send_event(event(gamepad));
+    harness.tick(ticks);
+
+    harness.translation(player) - start
+}
+
+#[test]
+fn gamepad_stick_scales_player_speed() {
+    let config = GameConfig::default();
+    let ticks = 10;
+    let travel = gamepad_player_travel(
+        |gamepad| RawGamepadEvent::Axis(RawGamepadAxisChangedEvent::new(gamepad, GamepadAxis::LeftStickX, 0.5)),
+        ticks,
+    );
+
+    let stick = apply_deadzone(Vec2::new(0.5, 0.0), config.gamepad_deadzone).x;
+    let full_speed_travel = config.player_speed * Time::<Fixed>::default().timestep().as_secs_f32() * ticks as f32;
+    assert!(travel.x > 0.0 && travel.x < full_speed_travel);
+    assert!((travel.x / full_speed_travel - stick).abs() < 0.02);
+    assert_eq!(travel.y, 0.0);
+}
+
+#[test]
+fn gamepad_stick_inside_deadzone_is_ignored() {
+    let travel = gamepad_player_travel(
+        |gamepad| RawGamepadEvent::Axis(RawGamepadAxisChangedEvent::new(gamepad, GamepadAxis::LeftStickY, 0.1)),
+        10,
+    );
+
+    assert_eq!(travel, Vec3::ZERO);
+}
+
+#[test]
+fn gamepad_dpad_moves_at_full_speed() {
+    let config = GameConfig::default();
+    let ticks = 10;
+    let travel = gamepad_player_travel(
+        |gamepad| RawGamepadEvent::Button(RawGamepadButtonChangedEvent::new(gamepad, GamepadButton::DPadUp, 1.0)),
+        ticks,
+    );
+
+    let full_speed_travel = config.player_speed * Time::<Fixed>::default().timestep().as_secs_f32() * ticks as f32;
+    assert!((travel.y - full_speed_travel).abs() < 1e-3);
+}
+
+#[test]
+fn rebound_action_moves_the_player() {
+    let mut harness = Harness::new();
+    harness
+        .app
+        .world_mut()
+        .resource_mut::<InputMap>()
+        .bindings
+        .insert(Action::MoveRight, vec![Binding::Key(KeyCode::KeyL)]);
+    harness.start_game();
+    let player = harness.player().unwrap();
+
+    let mut keyboard_input = harness.app.world_mut().resource_mut::<ButtonInput<KeyCode>>();
+    keyboard_input.press(KeyCode::KeyD);
+    harness.tick(5);
+    assert_eq!(harness.translation(player).x, 0.0);
+
+    let mut keyboard_input = harness.app.world_mut().resource_mut::<ButtonInput<KeyCode>>();
+    keyboard_input.release(KeyCode::KeyD);
+    keyboard_input.press(KeyCode::KeyL);
+    harness.tick(5);
+    assert!(harness.translation(player).x > 0.0);
+}
+
+#[test]
+fn coop_players_move_with_their_own_keys() {
+    let mut harness = Harness::new();
+    harness.start_coop_game();
+    let [(0, first), (1, second)] = harness.players()[..] else {
+        panic!("expected two players");
+    };
+    let first_start = harness.translation(first);
+    let second_start = harness.translation(second);
+    assert_ne!(first_start, second_start);
+
+    harness.app.world_mut().resource_mut::<ButtonInput<KeyCode>>().press(KeyCode::KeyD);
+    harness.tick(5);
+    assert!(harness.translation(first).x > first_start.x);
+    assert_eq!(harness.translation(second), second_start);
+
+    let mut keyboard_input = harness.app.world_mut().resource_mut::<ButtonInput<KeyCode>>();
+    keyboard_input.release(KeyCode::KeyD);
+    keyboard_input.press(KeyCode::ArrowUp);
+    let first_stop = harness.translation(first);
+    harness.tick(5);
+    assert_eq!(harness.translation(first), first_stop);
+    assert!(harness.translation(second).y > second_start.y);
+
+    let tint = |harness: &Harness, player: Entity| harness.app.world().get::<Sprite>(player).unwrap().color;
+    assert_ne!(tint(&harness, first), tint(&harness, second));
+}
+
+#[test]
+fn coop_run_lasts_until_both_players_are_out() {
+    let mut harness = Harness::new();
+    harness.start_coop_game();
+    let [(0, first), (1, second)] = harness.players()[..] else {
+        panic!("expected two players");
+    };
+
+    for player in [first, second] {
+        harness.set_lives(player, 1);
+        let position = harness.translation(player);
+        harness.spawn_enemy(position, Vec2::X, 0.0);
+        harness.tick(2);
+
+        if player == first {
+            assert_eq!(harness.players(), vec![(1, second)]);
+            assert_eq!(harness.state(), GameState::InGame);
+        }
+    }
+
+    assert!(harness.players().is_empty());
+    assert_eq!(harness.state(), GameState::GameOver);
+}
+
+#[test]
+fn coop_stars_score_for_the_player_who_collects_them() {
+    let mut harness = Harness::new();
+    harness.start_coop_game();
+    let second = harness.players()[1].1;
+    let position = harness.translation(second);
+    harness.app.world_mut().spawn((Transform::from_translation(position), Star {}));
+    harness.tick(2);
+
+    let score = harness.app.world().resource::<Score>();
+    assert!(score.players[1] >= 1);
+    assert_eq!(score.value, score.players[0] + score.players[1]);
+}
+
+#[test]
+fn restoring_a_snapshot_plays_the_same_ticks_again() {
+    let mut harness = Harness::new();
+    harness.start_game();
+    let enemies = [
+        (Vec3::new(300.0, 100.0, 0.0), Vec2::NEG_X),
+        (Vec3::new(-200.0, -150.0, 0.0), Vec2::ONE),
+    ];
+    for (position, direction) in enemies {
+        let enemy = harness.spawn_enemy(position, direction.normalize(), 200.0);
+        harness.app.world_mut().entity_mut(enemy).insert(EnemyBehaviour::Chaser);
+    }
+    aim_right(&mut harness);
+    harness.app.world_mut().resource_mut::<ButtonInput<KeyCode>>().press(KeyCode::KeyW);
+    harness.tick(10);
+
+    let snapshot = GameSnapshot::save(harness.app.world_mut());
+    harness.tick(40);
+    let expected = GameSnapshot::save(harness.app.world_mut()).checksum();
+    assert_ne!(snapshot.checksum(), expected);
+
+    snapshot.restore(harness.app.world_mut());
+    assert_eq!(GameSnapshot::save(harness.app.world_mut()).checksum(), snapshot.checksum());
+    harness.tick(40);
+    assert_eq!(GameSnapshot::save(harness.app.world_mut()).checksum(), expected);
+}
+
+#[test]
+fn a_rollback_credits_a_shared_kill_to_the_same_player() {
+    let mut harness = Harness::new();
+    harness.start_coop_game();
+    let stars: Vec<Entity> = harness
+        .app
+        .world_mut()
+        .query_filtered::<Entity, With<Star>>()
+        .iter(harness.app.world())
+        .collect();
+    for star in stars {
+        harness.app.world_mut().despawn(star);
+    }
+    // Stars may have been collected while the run started.
+    *harness.app.world_mut().resource_mut::<Score>() = Score::default();
+    // Both players' projectiles reach the same enemy on the same tick.
+    let position = Vec3::new(0.0, 250.0, 0.0);
+    harness.spawn_enemy(position, Vec2::X, 0.0);
+    for owner in 0..2 {
+        let entity = harness.app.world_mut().resource_mut::<projectile::ProjectilePool>().free.pop().unwrap();
+        let mut projectile = harness.app.world_mut().entity_mut(entity);
+        *projectile.get_mut::<Projectile>().unwrap() = Projectile {
+            velocity: Vec2::ZERO,
+            in_flight: true,
+            owner,
+        };
+        projectile.get_mut::<Transform>().unwrap().translation = position;
+        *projectile.get_mut::<Visibility>().unwrap() = Visibility::Inherited;
+    }
+
+    let snapshot = GameSnapshot::save(harness.app.world_mut());
+    harness.tick(1);
+    let expected = harness.app.world().resource::<Score>().players;
+    assert_eq!(expected.iter().sum::<u32>(), projectile::ENEMY_POINTS);
+
+    snapshot.restore(harness.app.world_mut());
+    harness.tick(1);
+    assert_eq!(harness.app.world().resource::<Score>().players, expected);
+}
+
+/// Two instances of the game playing each other over loopback, the first seeded with 11 and the
+/// second with 22, ticked in turn until the handshake has started the run on both.
+fn connect_netplay() -> (Harness, Harness) {
+    let sockets = [UdpSocket::bind("127.0.0.1:0").unwrap(), UdpSocket::bind("127.0.0.1:0").unwrap()];
+    let addresses = [sockets[0].local_addr().unwrap(), sockets[1].local_addr().unwrap()];
+    let [mut first, mut second] = sockets.map(|socket| {
+        let local_player = usize::from(socket.local_addr().unwrap() == addresses[1]);
+        let peer = addresses[1 - local_player];
+        let mut harness = Harness::new();
+        harness.app.insert_resource(PlayerCount(2));
+        harness.app.insert_resource(SeedConfig {
+            seed: Some(11 * (local_player as u64 + 1)),
+        });
+        harness.app.insert_resource(NetplaySession::new(socket, peer, local_player).unwrap());
+        harness
+    });
+
+    for _ in 0..10 {
+        first.tick(1);
+        second.tick(1);
+    }
+    assert_eq!(first.state(), GameState::InGame);
+    assert_eq!(second.state(), GameState::InGame);
+    (first, second)
+}
+
+#[test]
+fn netplay_plays_the_first_players_seed() {
+    let (mut first, mut second) = connect_netplay();
+
+    for harness in [&mut first, &mut second] {
+        assert_eq!(harness.app.world().resource::<GameRng>().seed, 11);
+        assert_eq!(harness.players().len(), 2);
+    }
+}
+
+#[test]
+fn netplay_rolls_back_mispredicted_input_and_stays_in_sync() {
+    let (mut first, mut second) = connect_netplay();
+
+    // The first instance runs ahead predicting the second player stands still, until it has to wait.
+    first.tick(20);
+    assert_eq!(first.session().frame(), second.session().frame() + MAX_PREDICTION);
+
+    // Meanwhile the second player moves, so the first instance predicted wrong.
+    second.app.world_mut().resource_mut::<ButtonInput<KeyCode>>().press(KeyCode::KeyD);
+    second.tick(5);
+    for _ in 0..60 {
+        first.tick(1);
+        second.tick(1);
+    }
+
+    assert!(first.session().rollbacks > 0);
+    for harness in [&first, &second] {
+        assert!(harness.session().checked_frames > 0);
+        assert_eq!(harness.session().desync, None);
+    }
+    let frame = first.session().frame().min(second.session().frame()) - MAX_PREDICTION;
+    assert_eq!(first.session().checksum(frame), second.session().checksum(frame));
+}
+
+#[test]
+fn netplay_reports_a_desync() {
+    let (mut first, mut second) = connect_netplay();
+    for _ in 0..10 {
+        first.tick(1);
+        second.tick(1);
+    }
+
+    first.app.world_mut().resource_mut::<Score>().value += 100;
+    for _ in 0..30 {
+        first.tick(1);
+        second.tick(1);
+    }
+
+    let desync = first.session().desync.or(second.session().desync).unwrap();
+    assert_ne!(desync.local, desync.remote);
+}
+
+#[test]
+fn only_live_runs_make_the_high_score_table() {
+    let high_scores_after_losing = |replay_mode: ReplayMode| {
+        let mut harness = Harness::new();
+        harness.app.insert_resource(replay_mode);
+        harness.start_game();
+        harness.app.world_mut().resource_mut::<Score>().value = 7;
+        let player = harness.player().unwrap();
+        harness.set_lives(player, 1);
+        let position = harness.translation(player);
+        harness.spawn_enemy(position, Vec2::X, 0.0);
+        harness.tick(2);
+        assert_eq!(harness.state(), GameState::GameOver);
+        harness.app.world().resource::<HighScores>().table.entries.len()
+    };
+
+    assert_eq!(high_scores_after_losing(ReplayMode::Off), 1);
+    let replay = Replay::new(0, Difficulty::default(), 1, GameConfig::default().checksum());
+    assert_eq!(high_scores_after_losing(ReplayMode::Playback { replay, tick: 0 }), 0);
+}
+
+#[test]
+fn playing_back_a_recording_reproduces_the_run() {
+    let path = std::env::temp_dir().join(format!("ball-game-playback-{}.ron", std::process::id()));
+    let start_run = |replay_mode: ReplayMode| {
+        let mut harness = Harness::new();
+        harness.app.insert_resource(SeedConfig { seed: Some(7) });
+        harness.app.insert_resource(replay_mode);
+        harness.app.world_mut().resource_mut::<NextState<GameState>>().set(GameState::InGame);
+        harness.tick(1);
+        harness
+    };
+
+    let replay = Replay::new(0, Difficulty::default(), 1, 0);
+    let mut recording = start_run(ReplayMode::Record {
+        path: path.clone(),
+        replay,
+        saved: false,
+    });
+    for key in [KeyCode::KeyD, KeyCode::KeyW, KeyCode::KeyA] {
+        recording.app.world_mut().resource_mut::<ButtonInput<KeyCode>>().press(key);
+        recording.tick(40);
+        recording.app.world_mut().resource_mut::<ButtonInput<KeyCode>>().release(key);
+    }
+    let expected = GameSnapshot::save(recording.app.world_mut()).checksum();
+    let Some(ReplayMode::Record { replay, .. }) = recording.app.world_mut().remove_resource::<ReplayMode>() else {
+        panic!("the recording stopped");
+    };
+    let _ = std::fs::remove_file(&path);
+    assert!(replay.ticks.iter().any(|inputs| inputs[0] != PlayerInput::default()));
+
+    let mut playback = start_run(ReplayMode::Playback { replay, tick: 0 });
+    playback.tick(120);
+    assert_eq!(GameSnapshot::save(playback.app.world_mut()).checksum(), expected);
+}
+
+#[test]
+fn an_edited_config_waits_for_the_next_run_while_recording() {
+    let mut harness = Harness::new();
+    let path = std::env::temp_dir().join(format!("ball-game-config-replay-{}.ron", std::process::id()));
+    let replay = Replay::new(0, Difficulty::default(), 1, GameConfig::default().checksum());
+    harness.app.insert_resource(ReplayMode::Record {
+        path,
+        replay,
+        saved: false,
+    });
+    let handle = harness.app.world().resource::<config::GameConfigHandle>().id();
+    for _ in 0..100 {
+        if harness.app.world().resource::<Assets<GameConfig>>().contains(handle) {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+        harness.tick(1);
+    }
+    harness.start_game();
+
+    let mut edited = GameConfig::default();
+    edited.player_speed /= 2.0;
+    harness.app.world_mut().resource_mut::<Assets<GameConfig>>().insert(handle, edited.clone());
+    harness.tick(2);
+    assert_eq!(*harness.app.world().resource::<GameConfig>(), GameConfig::default());
+
+    harness.app.world_mut().resource_mut::<NextState<GameState>>().set(GameState::MainMenu);
+    harness.tick(1);
+    harness.start_game();
+    assert_eq!(*harness.app.world().resource::<GameConfig>(), edited);
+}